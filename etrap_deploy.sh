@@ -100,6 +100,7 @@ near call etrap.testnet mint_batch '{
     "timestamp": 1749561600000,
     "tx_count": 10000,
     "merkle_root": "0x7d865e959b2466918c9863afca942d0fb89d7c9ac0c99bafc3749504ded97730",
+    "hash_algorithm": "sha256",
     "s3_bucket": "etrap-etrap",
     "s3_key": "trading_db/BATCH-2025-06-12-001/batch-data.json",
     "size_bytes": 5242880,
@@ -135,6 +136,7 @@ near call etrap.testnet mint_batch '{
     "timestamp": 1749583200000,
     "tx_count": 5000,
     "merkle_root": "0x892f97c4b6deed1c7a8e5f3b4e2d8a6c9f1e3b7d5a9c2e6f8b4d7a3e9c5f1b8d",
+    "hash_algorithm": "sha256",
     "s3_bucket": "etrap-etrap",
     "s3_key": "customer_db/BATCH-2025-06-12-002/batch-data.json",
     "size_bytes": 2621440,
@@ -170,6 +172,7 @@ near call etrap.testnet mint_batch '{
     "timestamp": 1749604800000,
     "tx_count": 2500,
     "merkle_root": "0xa3b7c9d2e4f6a8b1c3d5e7f9b2d4e6f8c1e3f5a7b9d2e4f6c8e1f3a5b7c9d1e3",
+    "hash_algorithm": "sha256",
     "s3_bucket": "etrap-etrap",
     "s3_key": "compliance_db/BATCH-2025-06-12-003/batch-data.json",
     "size_bytes": 1310720,
@@ -243,6 +246,7 @@ near view etrap.testnet nft_tokens_for_owner '{"account_id": "etrap.testnet", "l
 near view etrap.testnet verify_document_in_batch '{
   "token_id": "BATCH-2025-06-12-001",
   "document_hash": "0x1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef",
+  "leaf_encoding": "digest",
   "merkle_proof": [
     "0xabcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890",
     "0x567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234",
@@ -281,6 +285,7 @@ near call etrap.testnet mint_batch '{
     "timestamp": 1749475200000,
     "tx_count": 3000,
     "merkle_root": "0xb4c8d1e2f3a4b5c6d7e8f9a1b2c3d4e5f6a7b8c9d1e2f3a4b5c6d7e8f9a1b2c3",
+    "hash_algorithm": "sha256",
     "s3_bucket": "etrap-etrap",
     "s3_key": "healthcare_db/BATCH-2025-06-11-001/batch-data.json",
     "size_bytes": 1572864,
@@ -296,6 +301,12 @@ near call etrap.testnet mint_batch '{
 # 7. ADMIN FUNCTIONS (Only contract owner)
 #########################
 
+# Allow an ingestion worker account to mint batches
+near call etrap.testnet grant_role '{"role": "minter", "account_id": "ingest-worker.testnet"}' --accountId etrap.testnet
+
+# List accounts holding the minter role
+near view etrap.testnet get_role_members '{"role": "minter"}'
+
 # Pause the contract (emergency)
 near call etrap.testnet set_paused '{"paused": true}' --accountId etrap.testnet
 
@@ -305,6 +316,14 @@ near call etrap.testnet set_paused '{"paused": false}' --accountId etrap.testnet
 # Update treasury address
 near call etrap.testnet update_treasury '{"new_treasury": "new-etrap-treasury.testnet"}' --accountId etrap.testnet
 
+# Withdraw escrowed fees (run by the treasury account)
+near call etrap.testnet withdraw_fees '{}' --accountId etrap-treasury.testnet --depositYocto 1
+near view etrap.testnet get_fee_totals '{}'
+
+# Hand ownership to a separate admin account (two steps)
+near call etrap.testnet propose_owner '{"new_owner_id": "etrap-admin.testnet"}' --accountId etrap.testnet
+near call etrap.testnet accept_ownership '{}' --accountId etrap-admin.testnet
+
 #########################
 # 8. USEFUL QUERIES FOR TESTING
 #########################
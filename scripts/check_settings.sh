@@ -7,7 +7,7 @@ ETRAP NEAR Smart Contract - Settings Checker
 This script checks the current configuration settings of an ETRAP smart contract.
 
 What this script shows:
-- Current treasury address for fee collection
+- Current fee recipients and their shares
 - Fee amount configuration
 - Contract pause status
 - Organization information
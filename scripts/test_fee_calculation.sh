@@ -8,9 +8,8 @@ This script provides information about how ETRAP fees are calculated and
 shows the improvements made to the fee calculation logic.
 
 What this script explains:
-- Current fee calculation method (25% of attached deposit)
-- Previous behavior and why it was changed
-- Examples of fee calculations with different deposit amounts
+- Current fee calculation method (measured storage + fixed ETRAP fee)
+- Refund of excess deposit
 - Testing recommendations for fee validation
 
 Usage: ./test_fee_calculation.sh
@@ -20,28 +19,25 @@ No parameters required - this displays informational content about fee calculati
 
 echo "=== ETRAP Fee Calculation Test ==="
 echo ""
-echo "The fee calculation has been fixed to use the actual attached deposit"
-echo "instead of the estimated storage requirement."
+echo "mint_batch charges the storage it actually uses plus the fixed ETRAP fee"
+echo "and refunds the rest of the attached deposit to the caller."
 echo ""
-echo "OLD BEHAVIOR:"
-echo "- Estimate storage needed (4KB)"
-echo "- Calculate minimum total = storage / 0.75"
-echo "- Fee = minimum total - storage estimate"
-echo "- Problem: Fee was based on estimate, not actual payment"
+echo "HOW IT WORKS:"
+echo "- The contract records env::storage_usage() before minting"
+echo "- It mints the token and updates all indices"
+echo "- Storage cost = bytes added × storage byte cost (10^19 yoctoNEAR/byte)"
+echo "- Required = storage cost + ETRAP fee"
+echo "- Surplus deposit is refunded to the predecessor account"
 echo ""
-echo "NEW BEHAVIOR:"
-echo "- Check minimum deposit requirement"
-echo "- Fee = attached_deposit × 25%"
-echo "- Storage gets remaining 75%"
-echo ""
-echo "Example with 0.04 NEAR deposit:"
-echo "- Fee: 0.04 × 0.25 = 0.01 NEAR (exactly 25%)"
-echo "- Storage: 0.04 × 0.75 = 0.03 NEAR"
+echo "Example with 0.1 NEAR deposit, 0.01 NEAR fee and 1,200 bytes of storage:"
+echo "- Storage: 1200 × 0.00001 = 0.012 NEAR"
+echo "- Fee: 0.01 NEAR (escrowed for the fee recipients to withdraw)"
+echo "- Refund: 0.1 - 0.022 = 0.078 NEAR"
 echo ""
 echo "To deploy and test:"
 echo "1. Build: ./build.sh"
 echo "2. Deploy: near deploy --accountId <your-account>.testnet --wasmFile out/etrap_contract.wasm"
-echo "3. Test mint with exact deposit: near call <your-account>.testnet mint_batch '{...}' --deposit 0.04"
+echo "3. Test mint: near call <your-account>.testnet mint_batch '{...}' --deposit 0.1"
 echo ""
-echo "The fee will now be exactly 25% of whatever amount you attach,"
-echo "as long as it meets the minimum requirement."
\ No newline at end of file
+echo "If the deposit is short, the call fails with the exact storage cost,"
+echo "bytes used, fee and attached amount in the error message."
@@ -28,6 +28,7 @@ near call acme.testnet mint_batch "$(cat <<'EOF'
     "timestamp": 1734000000000,
     "tx_count": 4,
     "merkle_root": "d8648cbc02b4e08b84ee4b55dd01030b2f2ed48699cb7b76d015ca13efd55f24",
+    "hash_algorithm": "sha256",
     "s3_bucket": "etrap-test",
     "s3_key": "test_db/BATCH-TEST-SHA256-001/batch-data.json",
     "size_bytes": 1024,
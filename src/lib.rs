@@ -13,10 +13,16 @@
 // Key features:
 // - NEP-177 compliant NFT implementation
 // - Multi-tenant architecture (each organization deploys their own instance)
-// - Merkle tree verification for transaction integrity
+// - Merkle tree verification for transaction integrity (legacy, RFC 6962 and binary trees)
+// - Per-database Merkle Mountain Range committing to every batch in mint order
+// - Sparse Merkle tree state roots proving the latest version of a row
 // - Multiple indices for efficient querying (by database, time, table, etc.)
 // - Minimal on-chain storage with S3 references for detailed data
 // - Built-in fee collection mechanism for platform sustainability
+// - Role-based access control (owner, admin, minter, pauser)
+// - M-of-N approval of sensitive admin actions
+// - NEP-145 storage management for prepaid minter balances
+// - Fee escrow with pull-based withdrawal by fee recipients
 //
 // Copyright (c) 2025 Graziano Labs Corp. All rights reserved.
 
@@ -25,12 +31,19 @@ use near_sdk::store::{LookupMap, IterableMap, IterableSet, Vector};
 use near_sdk::json_types::U128;
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
-    env, near_bindgen, require, AccountId, BorshStorageKey, NearToken,
-    PanicOnDefault, Promise, PromiseOrValue,
+    assert_one_yocto, env, is_promise_success, near_bindgen, require, AccountId,
+    BorshStorageKey, Gas, NearToken, PanicOnDefault, Promise, PromiseOrValue,
 };
 use near_sdk::serde_json::json;
 use std::collections::HashMap;
 
+mod merkle;
+mod mmr;
+mod smt;
+
+use merkle::{bytes_to_hex, normalize_hash};
+pub use merkle::{HashAlgorithm, LeafEncoding, TreeVersion};
+
 // Re-export the NFT standard implementations
 pub use near_contract_standards::non_fungible_token::core::{
     NonFungibleTokenCore, NonFungibleTokenResolver,
@@ -41,11 +54,26 @@ pub use near_contract_standards::non_fungible_token::metadata::{
 };
 pub use near_contract_standards::non_fungible_token::{Token, TokenId};
 use near_contract_standards::non_fungible_token::NonFungibleToken;
+pub use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
 
 // Constants
 const DATA_IMAGE_SVG_ETRAP_ICON: &str = "data:image/svg+xml,%3Csvg%20xmlns%3D%22http%3A%2F%2Fwww.w3.org%2F2000%2Fsvg%22%20viewBox%3D%220%200%20100%20100%22%3E%3Ccircle%20cx%3D%2250%22%20cy%3D%2250%22%20r%3D%2240%22%20fill%3D%22%234A90E2%22%2F%3E%3Ctext%20x%3D%2250%22%20y%3D%2260%22%20text-anchor%3D%22middle%22%20fill%3D%22white%22%20font-size%3D%2230%22%20font-weight%3D%22bold%22%3EETRAP%3C%2Ftext%3E%3C%2Fsvg%3E";
 const RECENT_TOKENS_LIMIT: u64 = 100;
 const YOCTO_PER_NEAR: u128 = 1_000_000_000_000_000_000_000_000; // 10^24
+const NEAR_DECIMALS: usize = 24;
+const BASIS_POINTS: u128 = 10_000;
+const MAX_DISCOUNT_TIERS: usize = 10;
+const MAX_FEE_RECIPIENTS: usize = 10;
+const GAS_FOR_FEE_WITHDRAWAL_CALLBACK: Gas = Gas::from_tgas(10);
+const DEFAULT_PROPOSAL_DURATION_NS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000; // 7 days
+const MAX_APPROVERS: usize = 20;
+const MAX_BATCHES_PER_MINT: usize = 50;
+const MAX_CHAIN_WALK: u64 = 500;
+// Storage for one prepaid balance entry: key prefix + longest account ID (4 + 64)
+// + NearToken (16) + 40 bytes of per-record overhead
+const STORAGE_BALANCE_ENTRY_BYTES: u128 = 1 + 4 + 64 + 16 + 40;
 
 #[derive(BorshSerialize, BorshStorageKey)]
 enum StorageKey {
@@ -64,9 +92,38 @@ enum StorageKey {
     TokensByTableInner { table_hash: Vec<u8> },
     TotalBatchesPerDatabase,
     DatabaseList,
+    RoleMembers,
+    RoleMembersInner { role: Role },
+    AdminProposals,
+    StorageBalances,
+    FeeHistory,
+    MonthlyMintCounts,
+    FeeBalances,
+    MerkleSummaries,
+    MmrNodes,
+    MmrLeafCounts,
+    MmrLeafIndices,
+    DatabaseHeads,
+    TableStateRoots,
+    VersionedBatchSummaries,
+    BatchAnchors,
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+// Roles that can be granted to accounts. The owner implicitly holds every role.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum Role {
+    // Can grant and revoke the minter and pauser roles
+    Admin,
+    // Can call mint_batch
+    Minter,
+    // Can pause and unpause minting
+    Pauser,
+}
+
+// Batch summary as taken by mint_batch and returned by the views. It is
+// stored as a VersionedBatchSummary.
+#[derive(Serialize, Deserialize, Clone, Default)]
 #[serde(crate = "near_sdk::serde")]
 pub struct BatchSummary {
     pub database_name: String,
@@ -78,9 +135,145 @@ pub struct BatchSummary {
     pub s3_key: String,
     pub size_bytes: u64,
     pub operation_counts: OperationCounts,
+    // Tree scheme of merkle_root. Summaries stored as BatchSummaryV0 are legacy.
+    #[serde(default)]
+    pub tree_version: TreeVersion,
+    // Hash function of merkle_root. Required when minting; only batches stored
+    // before that lack it, and their algorithm is inferred from the root.
+    #[serde(default)]
+    pub hash_algorithm: Option<HashAlgorithm>,
+    // Number of leaves in the tree. Defaults to tx_count at mint time; only
+    // batches minted before it was recorded lack it.
+    #[serde(default)]
+    pub leaf_count: Option<u32>,
+    // Previous batch of the same database and its merkle root. Must name the
+    // database's current head; absent only for a database's first batch.
+    #[serde(default)]
+    pub previous_batch_id: Option<TokenId>,
+    #[serde(default)]
+    pub previous_root: Option<String>,
+    // Leaves are sorted by document hash, so absence can be proven
+    #[serde(default)]
+    pub sorted_leaves: bool,
+    // Hex root of the sparse Merkle tree of the database's latest row
+    // versions after applying this batch
+    #[serde(default)]
+    pub state_root: Option<String>,
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+impl BatchSummary {
+    pub fn effective_hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+            .unwrap_or_else(|| HashAlgorithm::infer(&self.merkle_root))
+    }
+}
+
+// Batch summary as stored before the tree scheme and chain fields were added
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
+pub struct BatchSummaryV0 {
+    pub database_name: String,
+    pub table_names: Vec<String>,
+    pub timestamp: u64,
+    pub tx_count: u32,
+    pub merkle_root: String,
+    pub s3_bucket: String,
+    pub s3_key: String,
+    pub size_bytes: u64,
+    pub operation_counts: OperationCounts,
+}
+
+// Batch summary as stored with the tree scheme, chain and state root fields
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
+pub struct BatchSummaryV1 {
+    pub database_name: String,
+    pub table_names: Vec<String>,
+    pub timestamp: u64,
+    pub tx_count: u32,
+    pub merkle_root: String,
+    pub s3_bucket: String,
+    pub s3_key: String,
+    pub size_bytes: u64,
+    pub operation_counts: OperationCounts,
+    pub tree_version: TreeVersion,
+    pub hash_algorithm: Option<HashAlgorithm>,
+    pub leaf_count: Option<u32>,
+    pub previous_batch_id: Option<TokenId>,
+    pub previous_root: Option<String>,
+    pub sorted_leaves: bool,
+    pub state_root: Option<String>,
+}
+
+// Stored form of a batch summary. Each variant's layout is frozen: adding a
+// field to BatchSummary means adding a struct and variant for the new layout
+// and storing new summaries in it, so summaries already stored keep
+// deserializing.
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
+pub enum VersionedBatchSummary {
+    V0(BatchSummaryV0),
+    V1(BatchSummaryV1),
+}
+
+impl From<BatchSummary> for VersionedBatchSummary {
+    fn from(summary: BatchSummary) -> Self {
+        Self::V1(BatchSummaryV1 {
+            database_name: summary.database_name,
+            table_names: summary.table_names,
+            timestamp: summary.timestamp,
+            tx_count: summary.tx_count,
+            merkle_root: summary.merkle_root,
+            s3_bucket: summary.s3_bucket,
+            s3_key: summary.s3_key,
+            size_bytes: summary.size_bytes,
+            operation_counts: summary.operation_counts,
+            tree_version: summary.tree_version,
+            hash_algorithm: summary.hash_algorithm,
+            leaf_count: summary.leaf_count,
+            previous_batch_id: summary.previous_batch_id,
+            previous_root: summary.previous_root,
+            sorted_leaves: summary.sorted_leaves,
+            state_root: summary.state_root,
+        })
+    }
+}
+
+impl From<VersionedBatchSummary> for BatchSummary {
+    fn from(summary: VersionedBatchSummary) -> Self {
+        match summary {
+            VersionedBatchSummary::V0(v0) => BatchSummary {
+                database_name: v0.database_name,
+                table_names: v0.table_names,
+                timestamp: v0.timestamp,
+                tx_count: v0.tx_count,
+                merkle_root: v0.merkle_root,
+                s3_bucket: v0.s3_bucket,
+                s3_key: v0.s3_key,
+                size_bytes: v0.size_bytes,
+                operation_counts: v0.operation_counts,
+                ..Default::default()
+            },
+            VersionedBatchSummary::V1(v1) => BatchSummary {
+                database_name: v1.database_name,
+                table_names: v1.table_names,
+                timestamp: v1.timestamp,
+                tx_count: v1.tx_count,
+                merkle_root: v1.merkle_root,
+                s3_bucket: v1.s3_bucket,
+                s3_key: v1.s3_key,
+                size_bytes: v1.size_bytes,
+                operation_counts: v1.operation_counts,
+                tree_version: v1.tree_version,
+                hash_algorithm: v1.hash_algorithm,
+                leaf_count: v1.leaf_count,
+                previous_batch_id: v1.previous_batch_id,
+                previous_root: v1.previous_root,
+                sorted_leaves: v1.sorted_leaves,
+                state_root: v1.state_root,
+            },
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
 #[serde(crate = "near_sdk::serde")]
 pub struct OperationCounts {
     pub inserts: u32,
@@ -88,7 +281,7 @@ pub struct OperationCounts {
     pub deletes: u32,
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
 #[serde(crate = "near_sdk::serde")]
 pub struct MerkleTreeBatchSummary {
     // Essential verification data
@@ -108,11 +301,28 @@ pub struct MerkleTreeBatchSummary {
     pub s3_bucket: String,
     pub s3_prefix: String,
     
-    // Blockchain anchoring
+    // Blockchain anchoring, set by the contract
+    #[serde(default)]
     pub anchoring_data: AnchoringData,
+    
+    // Link to the database's previous batch, as in BatchSummary
+    #[serde(default)]
+    pub previous_batch_id: Option<TokenId>,
+    #[serde(default)]
+    pub previous_root: Option<String>,
+    
+    // Leaves are sorted by document hash, as in BatchSummary
+    #[serde(default)]
+    pub sorted_leaves: bool,
+    
+    // Tree scheme of merkle_root and state root after the batch, as in BatchSummary
+    #[serde(default)]
+    pub tree_version: TreeVersion,
+    #[serde(default)]
+    pub state_root: Option<String>,
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
 #[serde(crate = "near_sdk::serde")]
 pub struct BatchSummaryInfo {
     pub start_timestamp: u64,
@@ -121,7 +331,7 @@ pub struct BatchSummaryInfo {
     pub operations_summary: OperationsSummary,
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
 #[serde(crate = "near_sdk::serde")]
 pub struct OperationsSummary {
     pub inserts: u32,
@@ -129,22 +339,361 @@ pub struct OperationsSummary {
     pub deletes: u32,
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+// Filled in by the contract when minting. There is no transaction hash: a
+// contract cannot see the hash of the transaction calling it, and a value
+// supplied by the minter would be unverified.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
 #[serde(crate = "near_sdk::serde")]
 pub struct AnchoringData {
     pub block_height: u64,
-    pub tx_hash: String,
     pub gas_used: String,
     pub etrap_fee: String,
 }
 
+// Block a batch was minted in, recorded by the contract for every mint
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy)]
+pub struct BatchAnchor {
+    // Nanoseconds, as env::block_timestamp
+    pub block_timestamp: u64,
+    pub block_height: u64,
+}
+
+// Current state of a database's Merkle Mountain Range
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DatabaseMmr {
+    pub database_name: String,
+    pub leaf_count: u64,
+    // Hex peaks, leftmost (largest) first
+    pub peaks: Vec<String>,
+    pub root: String,
+}
+
+// Latest state root anchored for a table
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TableStateRoot {
+    pub database_name: String,
+    pub table_name: String,
+    // Batch that anchored the root
+    pub token_id: TokenId,
+    pub state_root: String,
+}
+
+// Latest version of a row, for building a state root
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RowState {
+    pub table_name: String,
+    pub primary_key_hash: String,
+    // 32-byte hex digest of the row version
+    pub row_hash: String,
+}
+
+// Proof that a batch is part of its database's history, against the current root
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MmrInclusionProof {
+    pub token_id: TokenId,
+    pub database_name: String,
+    pub leaf_index: u64,
+    pub leaf_count: u64,
+    pub leaf: String,
+    // Hex siblings from the leaf up to its peak
+    pub siblings: Vec<String>,
+    // Position of the leaf's peak in `peaks`
+    pub peak_index: u32,
+    pub peaks: Vec<String>,
+    pub root: String,
+}
+
+// Proof that a database's history at `new_size` batches extends the history
+// at `old_size` batches
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MmrConsistencyProof {
+    pub database_name: String,
+    pub old_size: u64,
+    pub new_size: u64,
+    // Hex peaks of the old range, leftmost first
+    pub old_peaks: Vec<String>,
+    // For each old peak, hex siblings up to the new peak containing it
+    pub old_peak_paths: Vec<Vec<String>>,
+    // Hex peaks of the new range, leftmost first
+    pub new_peaks: Vec<String>,
+}
+
+// Why a document failed to verify
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum VerificationFailure {
+    BatchNotFound,
+    // A supplied leaf_count differs from the batch's
+    LeafCountMismatch,
+    // leaf_index is not below the batch's leaf count
+    IndexOutOfRange,
+    // The proof has more or fewer elements than the leaf's path
+    BadProofLength,
+    // A proof element is not a 32-byte digest (byte-level trees)
+    InvalidProofElement,
+    // The document does not decode under the given leaf_encoding
+    InvalidDocument,
+    RootMismatch,
+}
+
+// Outcome of verify_document_detailed. Everything but `valid` and `failure`
+// is null when the batch doesn't exist.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DocumentVerification {
+    pub valid: bool,
+    pub failure: Option<VerificationFailure>,
+    // Root the proof leads to, in the form of the batch's merkle root; null
+    // if the proof could not be walked
+    pub computed_root: Option<String>,
+    pub expected_root: Option<String>,
+    pub tree_version: Option<TreeVersion>,
+    pub hash_algorithm: Option<HashAlgorithm>,
+    // Timestamp (ns) and height of the block the batch was minted in. Null
+    // for batches minted before the contract recorded them.
+    pub timestamp: Option<u64>,
+    pub block_height: Option<u64>,
+}
+
+// Inclusion proof of one leaf of a sorted tree
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NeighborProof {
+    pub document_hash: String,
+    pub leaf_index: u32,
+    pub merkle_proof: Vec<String>,
+}
+
+// Adjacent leaves bracketing an absent key. A key below the first leaf has
+// no left neighbor, and one above the last leaf no right neighbor.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NonMembershipProof {
+    pub left_neighbor_proof: Option<NeighborProof>,
+    pub right_neighbor_proof: Option<NeighborProof>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum ChainBreakKind {
+    // Not the database's first batch, but names no predecessor
+    Unlinked,
+    // The named predecessor does not exist
+    PreviousNotFound,
+    // The named predecessor belongs to another database
+    DatabaseMismatch,
+    // previous_root differs from the predecessor's merkle root
+    RootMismatch,
+    // Batches were minted between the predecessor and this batch
+    Gap,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChainBreak {
+    pub token_id: TokenId,
+    pub previous_batch_id: Option<TokenId>,
+    pub kind: ChainBreakKind,
+}
+
+// Result of walking a database's batch chain backwards
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChainVerification {
+    pub database_name: String,
+    pub batches_checked: u64,
+    pub breaks: Vec<ChainBreak>,
+    // Where to resume when the walk stopped at the limit
+    pub next_token_id: Option<TokenId>,
+    // Whether the walk reached the database's first batch
+    pub complete: bool,
+}
+
+// Arguments for a single batch in mint_batches
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MintArgs {
+    pub token_id: TokenId,
+    pub receiver_id: AccountId,
+    pub token_metadata: TokenMetadata,
+    pub batch_summary: BatchSummary,
+}
+
+// Fee amount in NEAR accepted by `new`: either a JSON number (0.01) or a
+// decimal string ("0.01"). Both are converted to yoctoNEAR without floating
+// point arithmetic.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde", untagged)]
+pub enum NearAmount {
+    Decimal(String),
+    Number(f64),
+}
+
+// Usage-based components added to the base fee (`ETRAPSettings::fee_amount`).
+// The total is clamped to [min_fee, max_fee]. All zero means a flat fee.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UsagePricing {
+    pub per_transaction_fee: NearToken,
+    pub per_byte_fee: NearToken,
+    pub min_fee: NearToken,
+    pub max_fee: Option<NearToken>,
+}
+
+// Volume discount applied once `min_batches` batches have been minted in the
+// current calendar month
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DiscountTier {
+    pub min_batches: u64,
+    pub discount_bps: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VolumeTierInfo {
+    pub month: String,
+    pub batches_this_month: u64,
+    pub current_tier: Option<DiscountTier>,
+    pub next_tier: Option<DiscountTier>,
+    pub batches_until_next_tier: Option<u64>,
+}
+
+// Account receiving `share_bps` basis points of every ETRAP fee
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeRecipient {
+    pub account_id: AccountId,
+    pub share_bps: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeShare {
+    pub account_id: AccountId,
+    pub amount: NearToken,
+}
+
+// Fees escrowed for a recipient. The available balance is
+// accrued - withdrawn - pending.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeBalance {
+    // Lifetime fees credited
+    pub accrued: NearToken,
+    // Lifetime fees successfully paid out
+    pub withdrawn: NearToken,
+    // Withdrawals whose transfer has not resolved yet
+    pub pending: NearToken,
+}
+
+impl FeeBalance {
+    pub fn available(&self) -> NearToken {
+        self.accrued
+            .saturating_sub(self.withdrawn)
+            .saturating_sub(self.pending)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeBalanceView {
+    pub accrued: NearToken,
+    pub withdrawn: NearToken,
+    pub pending: NearToken,
+    pub available: NearToken,
+}
+
+impl From<&FeeBalance> for FeeBalanceView {
+    fn from(balance: &FeeBalance) -> Self {
+        Self {
+            accrued: balance.accrued,
+            withdrawn: balance.withdrawn,
+            pending: balance.pending,
+            available: balance.available(),
+        }
+    }
+}
+
+// Fee charged for a single batch, broken down into its components
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeBreakdown {
+    pub base_fee: NearToken,
+    pub transaction_fee: NearToken,
+    pub size_fee: NearToken,
+    // Sum of the components before applying min/max caps
+    pub subtotal: NearToken,
+    // Monthly volume discount taken off the capped fee
+    pub discount_bps: u16,
+    pub discount: NearToken,
+    pub total_fee: NearToken,
+    // How total_fee is distributed across the fee recipients
+    pub split: Vec<FeeShare>,
+}
+
+// A fee configuration in effect from the given block onwards
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeRecord {
+    pub fee_amount: NearToken,
+    pub pricing: UsagePricing,
+    pub discount_tiers: Vec<DiscountTier>,
+    pub effective_from_block_height: u64,
+    pub effective_from_timestamp: u64,
+}
+
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct ETRAPSettings {
+    // Base fee charged for every batch
     pub fee_amount: NearToken,
-    pub etrap_treasury: AccountId,
+    pub pricing: UsagePricing,
+    // Sorted by ascending min_batches
+    pub discount_tiers: Vec<DiscountTier>,
+    // Shares sum to 10000 basis points. Rounding dust goes to the first recipient.
+    pub fee_recipients: Vec<FeeRecipient>,
     pub paused: bool,
 }
 
+// Threshold approval configuration. A threshold of 0 disables approvals and
+// sensitive actions can be performed directly by the owner.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ApprovalConfig {
+    pub approvers: Vec<AccountId>,
+    pub threshold: u32,
+    pub proposal_duration_ns: u64,
+}
+
+// Sensitive admin actions that require M-of-N approval once enabled
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde", tag = "type", rename_all = "snake_case")]
+pub enum AdminAction {
+    UpdateTreasury { new_treasury: AccountId },
+    SetPaused { paused: bool },
+    SetApprovalConfig { config: ApprovalConfig },
+    SetFee { fee_amount: U128 },
+    SetFeePricing { pricing: UsagePricing },
+    SetDiscountTiers { tiers: Vec<DiscountTier> },
+    SetFeeRecipients { recipients: Vec<FeeRecipient> },
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AdminProposal {
+    pub id: u64,
+    pub action: AdminAction,
+    pub proposer: AccountId,
+    pub approvals: Vec<AccountId>,
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct BatchInfo {
@@ -170,7 +719,10 @@ pub struct ETRAPContract {
     metadata: NFTContractMetadata,
     
     // Lightweight batch summaries
-    batch_summaries: LookupMap<TokenId, BatchSummary>,
+    batch_summaries: LookupMap<TokenId, VersionedBatchSummary>,
+    // Summaries stored before they were versioned, under the original prefix.
+    // Read only; every new batch goes to batch_summaries.
+    legacy_batch_summaries: LookupMap<TokenId, BatchSummaryV0>,
     
     // Index 1: Database name → Set of token IDs
     tokens_by_database: LookupMap<String, IterableSet<TokenId>>,
@@ -193,22 +745,103 @@ pub struct ETRAPContract {
     
     // ETRAP-specific settings
     etrap_settings: ETRAPSettings,
+    
+    // Access control. The owner is independent of the contract account and
+    // becomes None once ownership is renounced.
+    owner_id: Option<AccountId>,
+    pending_owner_id: Option<AccountId>,
+    role_members: LookupMap<Role, IterableSet<AccountId>>,
+    
+    // Threshold approvals for sensitive admin actions
+    approval_config: ApprovalConfig,
+    admin_proposals: IterableMap<u64, AdminProposal>,
+    next_proposal_id: u64,
+    
+    // NEP-145 prepaid balances used to pay for minting
+    storage_balances: LookupMap<AccountId, NearToken>,
+    
+    // Every fee that has been in effect, oldest first
+    fee_history: Vector<FeeRecord>,
+    
+    // Year-Month (of the minting block) → number of batches minted
+    monthly_mint_counts: LookupMap<String, u64>,
+    
+    // Escrowed fees per fee recipient, and totals across all recipients
+    fee_balances: LookupMap<AccountId, FeeBalance>,
+    fee_totals: FeeBalance,
+    
+    // Token ID → full summary of batches minted with mint_merkle_batch
+    merkle_summaries: LookupMap<TokenId, MerkleTreeBatchSummary>,
+    
+    // Merkle Mountain Range of every database's batches:
+    // (sha256(database), level, index) → node, database → leaves, token → leaf index
+    mmr_nodes: LookupMap<(Vec<u8>, u8, u64), Vec<u8>>,
+    mmr_leaf_counts: LookupMap<String, u64>,
+    mmr_leaf_indices: LookupMap<TokenId, u64>,
+    
+    // Database → latest batch, which the next batch must link to
+    database_heads: LookupMap<String, TokenId>,
+    
+    // (database, table) → latest batch anchoring a state root for the table
+    table_state_roots: LookupMap<(String, String), TokenId>,
+    
+    // Token ID → block the batch was minted in
+    batch_anchors: LookupMap<TokenId, BatchAnchor>,
+}
+
+// Settings and contract state as deployed before access control, fee escrow
+// and batch history were added. Only read by migrate.
+#[derive(BorshDeserialize, BorshSerialize)]
+struct ETRAPSettingsV0 {
+    fee_amount: NearToken,
+    etrap_treasury: AccountId,
+    paused: bool,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+struct ETRAPContractV0 {
+    tokens: NonFungibleToken,
+    metadata: NFTContractMetadata,
+    batch_summaries: LookupMap<TokenId, BatchSummaryV0>,
+    tokens_by_database: LookupMap<String, IterableSet<TokenId>>,
+    tokens_by_month: LookupMap<String, Vector<TokenId>>,
+    tokens_by_timestamp: IterableMap<u64, TokenId>,
+    recent_tokens: Vector<TokenId>,
+    tokens_by_table: LookupMap<String, IterableSet<TokenId>>,
+    total_batches_per_database: LookupMap<String, u64>,
+    database_list: IterableSet<String>,
+    etrap_settings: ETRAPSettingsV0,
 }
 
 // Helper functions
 impl ETRAPContract {
     fn timestamp_to_year_month(timestamp: u64) -> String {
-        // Convert Unix timestamp (milliseconds) to YYYY-MM format
-        let seconds = timestamp / 1000;
-        let days = seconds / 86400;
-        let years = 1970 + (days / 365);
-        let month = ((days % 365) / 30) + 1;
-        format!("{:04}-{:02}", years, month)
+        // Convert Unix timestamp (milliseconds) to YYYY-MM format (UTC) with
+        // the civil-from-days algorithm for the Gregorian calendar. Days are
+        // counted from 0000-03-01 so leap days fall at the end of a year.
+        let days = timestamp / 1000 / 86400 + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let march_based_month = (5 * day_of_year + 2) / 153;
+        let month = if march_based_month < 10 { march_based_month + 3 } else { march_based_month - 9 };
+        let year = era * 400 + year_of_era + u64::from(month <= 2);
+        format!("{:04}-{:02}", year, month)
+    }
+    
+    // Summary of a batch in the current layout, whichever layout it is stored in
+    fn internal_batch_summary(&self, token_id: &TokenId) -> Option<BatchSummary> {
+        self.batch_summaries
+            .get(token_id)
+            .cloned()
+            .or_else(|| self.legacy_batch_summaries.get(token_id).cloned().map(VersionedBatchSummary::V0))
+            .map(BatchSummary::from)
     }
     
     fn get_batch_info(&self, token_id: &TokenId) -> BatchInfo {
         let token = self.tokens.nft_token(token_id.clone()).expect("Token not found");
-        let batch_summary = self.batch_summaries.get(token_id).expect("Batch summary not found");
+        let batch_summary = self.internal_batch_summary(token_id).expect("Batch summary not found");
         
         BatchInfo {
             token_id: token_id.clone(),
@@ -231,700 +864,4298 @@ impl ETRAPContract {
         }
     }
     
-    // Helper function to normalize hash format (remove 0x prefix if present)
-    fn normalize_hash(hash: &str) -> String {
-        if hash.starts_with("0x") {
-            hash[2..].to_string()
-        } else {
-            hash.to_string()
-        }
+    // Parse a decimal NEAR amount (e.g. "0.01") into yoctoNEAR exactly
+    fn parse_near_amount(amount: &str) -> u128 {
+        let amount = amount.trim();
+        let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+        require!(
+            !whole.is_empty() || !fraction.is_empty(),
+            "Fee amount must be a decimal number"
+        );
+        require!(
+            whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()),
+            "Fee amount must be a non-negative decimal number"
+        );
+        require!(
+            fraction.len() <= NEAR_DECIMALS,
+            "Fee amount has more than 24 decimal places"
+        );
+        
+        let whole_yocto = if whole.is_empty() { 0 } else {
+            whole.parse::<u128>()
+                .ok()
+                .and_then(|w| w.checked_mul(YOCTO_PER_NEAR))
+                .expect("Fee amount is too large")
+        };
+        let fraction_yocto = if fraction.is_empty() { 0 } else {
+            format!("{:0<width$}", fraction, width = NEAR_DECIMALS).parse::<u128>().unwrap()
+        };
+        
+        whole_yocto.checked_add(fraction_yocto).expect("Fee amount is too large")
     }
     
-    // Helper function to convert bytes to hex string
-    fn bytes_to_hex(bytes: &[u8]) -> String {
-        bytes.iter()
-            .map(|b| format!("{:02x}", b))
-            .collect::<String>()
+    fn near_amount_to_yocto(amount: &NearAmount) -> u128 {
+        match amount {
+            NearAmount::Decimal(decimal) => Self::parse_near_amount(decimal),
+            NearAmount::Number(number) => {
+                require!(number.is_finite() && *number >= 0.0, "Fee amount must be non-negative");
+                // Display prints the shortest decimal that round-trips to the
+                // same f64, so 0.01 becomes "0.01" rather than 0.01000000000000000020816...
+                Self::parse_near_amount(&number.to_string())
+            }
+        }
     }
     
-    fn internal_mint_with_indices(
-        &mut self,
-        token_id: TokenId,
-        receiver_id: AccountId,
-        token_metadata: TokenMetadata,
-        batch_summary: BatchSummary,
-    ) -> Token {
-        // Extract searchable components
-        let database = batch_summary.database_name.clone();
-        let timestamp = batch_summary.timestamp;
-        let year_month = Self::timestamp_to_year_month(timestamp);
+    fn emit_event(event: &str, data: serde_json::Value) {
+        let event_data = json!({
+            "standard": "etrap",
+            "version": "1.0.0",
+            "event": event,
+            "data": [data]
+        });
         
-        // Mint the NFT
-        let token = self.tokens.internal_mint_with_refund(
-            token_id.clone(),
-            receiver_id.clone(),
-            Some(token_metadata),
-            None,
+        env::log_str(&format!("EVENT_JSON:{}", event_data));
+    }
+    
+    fn internal_has_role(&self, role: Role, account_id: &AccountId) -> bool {
+        if self.owner_id.as_ref() == Some(account_id) {
+            return true;
+        }
+        self.role_members
+            .get(&role)
+            .map(|members| members.contains(account_id))
+            .unwrap_or(false)
+    }
+    
+    fn assert_owner(&self) {
+        require!(
+            self.owner_id.as_ref() == Some(&env::predecessor_account_id()),
+            "Only the owner can call this method"
         );
-        
-        // Update all indices atomically
-        
-        // Index by database  
-        let mut db_tokens = self.tokens_by_database.remove(&database)
-            .unwrap_or_else(|| IterableSet::new(
-                StorageKey::TokensByDatabaseInner {
-                    database_hash: env::sha256(database.as_bytes())
-                }
-            ));
-        db_tokens.insert(token_id.clone());
-        self.tokens_by_database.insert(database.clone(), db_tokens);
-        
-        // Index by month
-        let mut month_tokens = self.tokens_by_month.remove(&year_month)
-            .unwrap_or_else(|| Vector::new(
-                StorageKey::TokensByMonthInner {
-                    month_hash: env::sha256(year_month.as_bytes())
-                }
-            ));
-        month_tokens.push(token_id.clone());
-        self.tokens_by_month.insert(year_month.clone(), month_tokens);
-        
-        // Index by timestamp
-        self.tokens_by_timestamp.insert(timestamp, token_id.clone());
-        
-        // Index by tables
-        for table in &batch_summary.table_names {
-            let mut table_tokens = self.tokens_by_table.remove(table)
-                .unwrap_or_else(|| IterableSet::new(
-                    StorageKey::TokensByTableInner {
-                        table_hash: env::sha256(table.as_bytes())
-                    }
-                ));
-            table_tokens.insert(token_id.clone());
-            self.tokens_by_table.insert(table.clone(), table_tokens);
+    }
+    
+    fn assert_role(&self, role: Role) {
+        require!(
+            self.internal_has_role(role, &env::predecessor_account_id()),
+            format!("Caller does not have the {:?} role", role)
+        );
+    }
+    
+    // Only the owner may manage admins; admins manage every other role
+    fn assert_can_manage_role(&self, role: Role) {
+        match role {
+            Role::Admin => self.assert_owner(),
+            _ => self.assert_role(Role::Admin),
+        }
+    }
+    
+    fn internal_grant_role(&mut self, role: Role, account_id: AccountId) -> bool {
+        let mut members = self.role_members.remove(&role)
+            .unwrap_or_else(|| IterableSet::new(StorageKey::RoleMembersInner { role }));
+        let added = members.insert(account_id);
+        self.role_members.insert(role, members);
+        added
+    }
+    
+    fn internal_revoke_role(&mut self, role: Role, account_id: &AccountId) -> bool {
+        match self.role_members.remove(&role) {
+            Some(mut members) => {
+                let removed = members.remove(account_id);
+                self.role_members.insert(role, members);
+                removed
+            }
+            None => false,
+        }
+    }
+    
+    fn approvals_required(&self) -> bool {
+        self.approval_config.threshold > 0
+    }
+    
+    fn is_approver(&self, account_id: &AccountId) -> bool {
+        self.approval_config.approvers.contains(account_id)
+    }
+    
+    fn assert_approver(&self) {
+        require!(
+            self.is_approver(&env::predecessor_account_id()),
+            "Only approvers can call this method"
+        );
+    }
+    
+    // Sensitive actions may only be performed directly while approvals are disabled
+    fn assert_direct_admin_action_allowed(&self) {
+        self.assert_owner();
+        require!(
+            !self.approvals_required(),
+            "This action requires threshold approval, use create_proposal"
+        );
+    }
+    
+    // Approvals from accounts removed from the approver set no longer count
+    fn count_valid_approvals(&self, proposal: &AdminProposal) -> u32 {
+        proposal.approvals.iter().filter(|a| self.is_approver(a)).count() as u32
+    }
+    
+    fn validate_approval_config(config: &ApprovalConfig) {
+        require!(config.approvers.len() <= MAX_APPROVERS, "Too many approvers");
+        require!(
+            config.threshold as usize <= config.approvers.len(),
+            "Threshold cannot exceed the number of approvers"
+        );
+        // Approvers without a threshold would leave sensitive actions unguarded
+        require!(
+            config.approvers.is_empty() || config.threshold >= 1,
+            "Threshold must be at least 1 when approvers are set"
+        );
+        require!(config.proposal_duration_ns > 0, "Proposal duration must be positive");
+        for (i, approver) in config.approvers.iter().enumerate() {
+            require!(!config.approvers[..i].contains(approver), "Duplicate approver");
+        }
+    }
+    
+    fn internal_execute_admin_action(&mut self, action: AdminAction) {
+        match action {
+            AdminAction::UpdateTreasury { new_treasury } => {
+                self.etrap_settings.fee_recipients = Self::single_fee_recipient(new_treasury);
+            }
+            AdminAction::SetFeeRecipients { recipients } => {
+                Self::validate_fee_recipients(&recipients);
+                self.etrap_settings.fee_recipients = recipients;
+            }
+            AdminAction::SetPaused { paused } => {
+                self.etrap_settings.paused = paused;
+            }
+            AdminAction::SetApprovalConfig { config } => {
+                Self::validate_approval_config(&config);
+                self.approval_config = config;
+            }
+            AdminAction::SetFee { fee_amount } => {
+                let pricing = self.etrap_settings.pricing.clone();
+                let tiers = self.etrap_settings.discount_tiers.clone();
+                self.internal_set_fee(NearToken::from_yoctonear(fee_amount.0), pricing, tiers);
+            }
+            AdminAction::SetFeePricing { pricing } => {
+                let tiers = self.etrap_settings.discount_tiers.clone();
+                self.internal_set_fee(self.etrap_settings.fee_amount, pricing, tiers);
+            }
+            AdminAction::SetDiscountTiers { tiers } => {
+                let pricing = self.etrap_settings.pricing.clone();
+                self.internal_set_fee(self.etrap_settings.fee_amount, pricing, tiers);
+            }
+        }
+    }
+    
+    fn validate_pricing(pricing: &UsagePricing) {
+        if let Some(max_fee) = pricing.max_fee {
+            require!(max_fee >= pricing.min_fee, "Maximum fee cannot be lower than the minimum fee");
+        }
+    }
+    
+    fn single_fee_recipient(account_id: AccountId) -> Vec<FeeRecipient> {
+        vec![FeeRecipient { account_id, share_bps: BASIS_POINTS as u16 }]
+    }
+    
+    fn validate_fee_recipients(recipients: &[FeeRecipient]) {
+        require!(!recipients.is_empty(), "At least one fee recipient is required");
+        require!(recipients.len() <= MAX_FEE_RECIPIENTS, "Too many fee recipients");
+        for (i, recipient) in recipients.iter().enumerate() {
+            require!(recipient.share_bps > 0, "Fee recipient shares must be positive");
+            require!(
+                !recipients[..i].iter().any(|r| r.account_id == recipient.account_id),
+                "Duplicate fee recipient"
+            );
         }
+        let total_bps: u128 = recipients.iter().map(|r| r.share_bps as u128).sum();
+        require!(total_bps == BASIS_POINTS, "Fee recipient shares must sum to 10000 basis points");
+    }
+    
+    // Split a fee by the recipients' shares. Each share is rounded down and the
+    // remaining dust is added to the first recipient, so the split always sums
+    // to the fee.
+    fn split_fee(&self, fee: NearToken) -> Vec<FeeShare> {
+        let mut split: Vec<FeeShare> = self.etrap_settings.fee_recipients
+            .iter()
+            .map(|recipient| FeeShare {
+                account_id: recipient.account_id.clone(),
+                amount: Self::basis_points_of(fee, recipient.share_bps as u128),
+            })
+            .collect();
         
-        // Update recent tokens cache (FIFO - First In First Out)
-        self.recent_tokens.push(token_id.clone());
-        if self.recent_tokens.len() > RECENT_TOKENS_LIMIT as u32 {
-            // Remove the oldest token (first element) while maintaining order
-            // Note: This is O(n) but necessary for maintaining chronological order
-            let mut new_recent = Vector::new(StorageKey::RecentTokens);
-            for i in 1..self.recent_tokens.len() {
-                new_recent.push(self.recent_tokens.get(i).unwrap().clone());
+        let distributed = split.iter()
+            .fold(NearToken::from_yoctonear(0), |sum, share| sum.saturating_add(share.amount));
+        split[0].amount = split[0].amount.saturating_add(fee.saturating_sub(distributed));
+        
+        split
+    }
+    
+    fn validate_discount_tiers(tiers: &[DiscountTier]) {
+        require!(tiers.len() <= MAX_DISCOUNT_TIERS, "Too many discount tiers");
+        for (i, tier) in tiers.iter().enumerate() {
+            require!(tier.discount_bps as u128 <= BASIS_POINTS, "Discount cannot exceed 10000 basis points");
+            if i > 0 {
+                require!(
+                    tier.min_batches > tiers[i - 1].min_batches,
+                    "Discount tiers must be sorted by strictly increasing min_batches"
+                );
             }
-            self.recent_tokens = new_recent;
         }
+    }
+    
+    fn fee_record(fee_amount: NearToken, pricing: UsagePricing, discount_tiers: Vec<DiscountTier>) -> FeeRecord {
+        FeeRecord {
+            fee_amount,
+            pricing,
+            discount_tiers,
+            effective_from_block_height: env::block_height(),
+            effective_from_timestamp: env::block_timestamp(),
+        }
+    }
+    
+    // Change the fee configuration and record it in the fee history for
+    // invoice reconciliation
+    fn internal_set_fee(&mut self, fee_amount: NearToken, pricing: UsagePricing, discount_tiers: Vec<DiscountTier>) {
+        Self::validate_pricing(&pricing);
+        Self::validate_discount_tiers(&discount_tiers);
         
-        // Store batch summary
-        self.batch_summaries.insert(token_id.clone(), batch_summary);
+        let previous_fee = self.etrap_settings.fee_amount;
+        let record = Self::fee_record(fee_amount, pricing.clone(), discount_tiers.clone());
         
-        // Update statistics
-        let count = self.total_batches_per_database.get(&database).copied().unwrap_or(0) + 1;
-        self.total_batches_per_database.insert(database.clone(), count);
-        self.database_list.insert(database);
+        self.etrap_settings.fee_amount = fee_amount;
+        self.etrap_settings.pricing = pricing.clone();
+        self.etrap_settings.discount_tiers = discount_tiers.clone();
+        self.fee_history.push(record.clone());
         
-        token
+        Self::emit_event("fee_changed", json!({
+            "previous_fee_amount": previous_fee.as_yoctonear().to_string(),
+            "fee_amount": fee_amount.as_yoctonear().to_string(),
+            "pricing": pricing,
+            "discount_tiers": discount_tiers,
+            "effective_from_block_height": record.effective_from_block_height,
+            "sender_id": env::predecessor_account_id(),
+        }));
     }
-}
-
-#[near_bindgen]
-impl ETRAPContract {
-    #[init]
-    pub fn new(
-        organization_id: AccountId,
-        organization_name: String,
-        etrap_treasury: AccountId,
-        etrap_fee_amount: f64,
-    ) -> Self {
-        require!(!env::state_exists(), "Already initialized");
-        require!(etrap_fee_amount >= 0.0, "Fee amount must be non-negative");
+    
+    fn internal_compute_fee(&self, batch_summary: &BatchSummary) -> FeeBreakdown {
+        let pricing = &self.etrap_settings.pricing;
+        let base_fee = self.etrap_settings.fee_amount;
+        let transaction_fee = pricing.per_transaction_fee.saturating_mul(batch_summary.tx_count as u128);
+        let size_fee = pricing.per_byte_fee.saturating_mul(batch_summary.size_bytes as u128);
+        let subtotal = base_fee.saturating_add(transaction_fee).saturating_add(size_fee);
         
-        // Convert NEAR to yoctoNEAR
-        let fee_amount_yocto = (etrap_fee_amount * YOCTO_PER_NEAR as f64) as u128;
+        let mut capped_fee = subtotal.max(pricing.min_fee);
+        if let Some(max_fee) = pricing.max_fee {
+            capped_fee = capped_fee.min(max_fee);
+        }
         
-        let metadata = NFTContractMetadata {
-            spec: NFT_METADATA_SPEC.to_string(),
-            name: format!("ETRAP Document Integrity Certificates - {}", organization_name),
-            symbol: format!("ETRAP-{}", organization_name.chars().take(4).collect::<String>().to_uppercase()),
-            icon: Some(DATA_IMAGE_SVG_ETRAP_ICON.to_string()),
-            base_uri: None,
-            reference: Some("https://etrap.io/contracts/nft".to_string()),
-            reference_hash: None,
-        };
+        let discount_bps = self.current_discount_tier()
+            .map(|tier| tier.discount_bps)
+            .unwrap_or(0);
+        let discount = Self::basis_points_of(capped_fee, discount_bps as u128);
+        let total_fee = capped_fee.saturating_sub(discount);
         
-        Self {
-            tokens: NonFungibleToken::new(
-                StorageKey::NonFungibleToken,
-                organization_id.clone(),
-                Some(StorageKey::TokenMetadata),
-                Some(StorageKey::Enumeration),
-                Some(StorageKey::Approval),
-            ),
-            metadata,
-            batch_summaries: LookupMap::new(StorageKey::BatchSummaries),
-            tokens_by_database: LookupMap::new(StorageKey::TokensByDatabase),
-            tokens_by_month: LookupMap::new(StorageKey::TokensByMonth),
-            tokens_by_timestamp: IterableMap::new(StorageKey::TokensByTimestamp),
-            recent_tokens: Vector::new(StorageKey::RecentTokens),
-            tokens_by_table: LookupMap::new(StorageKey::TokensByTable),
-            total_batches_per_database: LookupMap::new(StorageKey::TotalBatchesPerDatabase),
-            database_list: IterableSet::new(StorageKey::DatabaseList),
-            etrap_settings: ETRAPSettings {
-                fee_amount: NearToken::from_yoctonear(fee_amount_yocto),
-                etrap_treasury,
-                paused: false,
-            },
+        FeeBreakdown {
+            base_fee,
+            transaction_fee,
+            size_fee,
+            subtotal,
+            discount_bps,
+            discount,
+            total_fee,
+            split: self.split_fee(total_fee),
         }
     }
     
-    // Mint a new batch certificate NFT
-    #[payable]
-    pub fn mint_batch(
-        &mut self,
-        token_id: TokenId,
-        receiver_id: AccountId,
-        token_metadata: TokenMetadata,
-        batch_summary: BatchSummary,
-    ) -> Token {
+    // amount × bps / 10000, rounded down, without overflowing u128
+    fn basis_points_of(amount: NearToken, bps: u128) -> NearToken {
+        let yocto = amount.as_yoctonear();
+        NearToken::from_yoctonear(
+            yocto / BASIS_POINTS * bps + yocto % BASIS_POINTS * bps / BASIS_POINTS
+        )
+    }
+    
+    // Calendar month (YYYY-MM) of the current block, used for volume discounts
+    fn current_billing_month() -> String {
+        Self::timestamp_to_year_month(env::block_timestamp() / 1_000_000)
+    }
+    
+    fn batches_minted_this_month(&self) -> u64 {
+        self.monthly_mint_counts
+            .get(&Self::current_billing_month())
+            .copied()
+            .unwrap_or(0)
+    }
+    
+    // Highest tier whose threshold has been reached by batches already minted this month
+    fn current_discount_tier(&self) -> Option<&DiscountTier> {
+        let minted = self.batches_minted_this_month();
+        self.etrap_settings.discount_tiers
+            .iter()
+            .rev()
+            .find(|tier| minted >= tier.min_batches)
+    }
+    
+    fn assert_can_mint(&self) {
         // Check if contract is paused
         require!(!self.etrap_settings.paused, "Contract is paused");
         
-        // Validate token doesn't already exist
-        require!(
-            self.tokens.nft_token(token_id.clone()).is_none(),
-            "Token already exists"
-        );
+        // Only accounts holding the minter role can mint certificates
+        self.assert_role(Role::Minter);
+    }
+    
+    // Charge the storage actually used since `initial_storage_usage` plus the
+    // ETRAP fee. The attached deposit is used first and any shortfall is debited
+    // from the caller's prepaid storage balance. Surplus deposit is refunded.
+    fn settle_mint_deposit(&mut self, initial_storage_usage: u64, etrap_fee: NearToken) {
+        self.flush_mint_collections();
         
-        // Calculate and collect ETRAP fee
+        let caller = env::predecessor_account_id();
         let attached_deposit = env::attached_deposit();
-        let storage_deposit = NearToken::from_yoctonear(env::storage_byte_cost().as_yoctonear() * 4000); // Estimate 4KB storage
+        let storage_used = env::storage_usage().saturating_sub(initial_storage_usage);
+        let storage_cost = env::storage_byte_cost().saturating_mul(storage_used as u128);
+        
+        let total_required = storage_cost.saturating_add(etrap_fee);
         
-        // ETRAP fee is a fixed amount
-        let etrap_fee = self.etrap_settings.fee_amount;
+        if attached_deposit >= total_required {
+            let refund = attached_deposit.saturating_sub(total_required);
+            if refund > NearToken::from_yoctonear(0) {
+                Promise::new(caller).transfer(refund);
+            }
+            return;
+        }
         
-        let total_required = storage_deposit.saturating_add(etrap_fee);
+        let shortfall = total_required.saturating_sub(attached_deposit);
+        let prepaid_available = self.internal_storage_available(&caller);
         
         require!(
-            attached_deposit >= total_required,
-            format!("Insufficient deposit. Required: {} yoctoNEAR (storage: {}, fee: {})", 
+            prepaid_available >= shortfall,
+            format!("Insufficient deposit. Required: {} yoctoNEAR (storage: {} for {} bytes, fee: {}), attached: {}, prepaid available: {}", 
                 total_required.as_yoctonear(), 
-                storage_deposit.as_yoctonear(), 
-                etrap_fee.as_yoctonear())
+                storage_cost.as_yoctonear(), 
+                storage_used,
+                etrap_fee.as_yoctonear(),
+                attached_deposit.as_yoctonear(),
+                prepaid_available.as_yoctonear())
         );
         
-        // Transfer fee to ETRAP treasury
-        if etrap_fee > NearToken::from_yoctonear(0) {
-            Promise::new(self.etrap_settings.etrap_treasury.clone())
-                .transfer(etrap_fee);
+        let balance = self.storage_balances.get_mut(&caller).expect("Account is not registered");
+        *balance = balance.saturating_sub(shortfall);
+    }
+    
+    // Store collections only write their changes on flush or drop, so
+    // everything a mint touched is flushed before its storage is measured
+    fn flush_mint_collections(&mut self) {
+        self.batch_summaries.flush();
+        self.tokens_by_database.flush();
+        self.tokens_by_month.flush();
+        self.tokens_by_timestamp.flush();
+        self.recent_tokens.flush();
+        self.tokens_by_table.flush();
+        self.total_batches_per_database.flush();
+        self.database_list.flush();
+        self.monthly_mint_counts.flush();
+        self.fee_balances.flush();
+        self.merkle_summaries.flush();
+        self.mmr_nodes.flush();
+        self.mmr_leaf_counts.flush();
+        self.mmr_leaf_indices.flush();
+        self.database_heads.flush();
+        self.table_state_roots.flush();
+        self.batch_anchors.flush();
+    }
+    
+    fn storage_balance_min() -> NearToken {
+        env::storage_byte_cost().saturating_mul(STORAGE_BALANCE_ENTRY_BYTES)
+    }
+    
+    // Prepaid balance that can be spent on minting or withdrawn
+    fn internal_storage_available(&self, account_id: &AccountId) -> NearToken {
+        self.storage_balances
+            .get(account_id)
+            .map(|total| total.saturating_sub(Self::storage_balance_min()))
+            .unwrap_or(NearToken::from_yoctonear(0))
+    }
+    
+    fn internal_storage_balance_of(&self, account_id: &AccountId) -> Option<StorageBalance> {
+        self.storage_balances.get(account_id).map(|total| StorageBalance {
+            total: *total,
+            available: total.saturating_sub(Self::storage_balance_min()),
+        })
+    }
+    
+    // Credit the split of every fee to the recipients' escrowed balances
+    fn accrue_fees(&mut self, fees: &[FeeBreakdown]) {
+        for share in fees.iter().flat_map(|fee| fee.split.iter()) {
+            if share.amount == NearToken::from_yoctonear(0) {
+                continue;
+            }
+            let mut balance = self.fee_balances.get(&share.account_id).cloned().unwrap_or_default();
+            balance.accrued = balance.accrued.saturating_add(share.amount);
+            self.fee_balances.insert(share.account_id.clone(), balance);
+            
+            self.fee_totals.accrued = self.fee_totals.accrued.saturating_add(share.amount);
         }
-        
-        // Mint with indices
-        let token = self.internal_mint_with_indices(
-            token_id.clone(),
-            receiver_id.clone(),
-            token_metadata.clone(),
-            batch_summary.clone(),
-        );
-        
-        // Emit detailed event for off-chain indexers
+    }
+    
+    fn mint_event_entry(
+        &self,
+        receiver_id: &AccountId,
+        token_id: &TokenId,
+        batch_summary: &BatchSummary,
+        fee: &FeeBreakdown,
+    ) -> serde_json::Value {
+        json!({
+            "owner_id": receiver_id.to_string(),
+            "token_ids": [token_id],
+            "batch_summary": {
+                "database": batch_summary.database_name,
+                "timestamp": batch_summary.timestamp,
+                "merkle_root": batch_summary.merkle_root,
+                "tx_count": batch_summary.tx_count,
+                "tables": batch_summary.table_names,
+                "s3_location": {
+                    "bucket": batch_summary.s3_bucket,
+                    "key": batch_summary.s3_key
+                }
+            },
+            "fee_info": {
+                "etrap_fee": fee.total_fee.as_yoctonear().to_string(),
+                "split": fee.split,
+                "breakdown": fee
+            }
+        })
+    }
+    
+    // Emit a single NEP-171 mint event for off-chain indexers
+    fn emit_mint_event(entries: Vec<serde_json::Value>) {
         let event_data = json!({
             "standard": "nep171",
             "version": "1.0.0",
             "event": "nft_mint",
-            "data": [{
-                "owner_id": receiver_id.to_string(),
-                "token_ids": [token_id.clone()],
-                "batch_summary": {
-                    "database": batch_summary.database_name,
-                    "timestamp": batch_summary.timestamp,
-                    "merkle_root": batch_summary.merkle_root,
-                    "tx_count": batch_summary.tx_count,
-                    "tables": batch_summary.table_names,
-                    "s3_location": {
-                        "bucket": batch_summary.s3_bucket,
-                        "key": batch_summary.s3_key
-                    }
-                },
-                "fee_info": {
-                    "etrap_fee": etrap_fee.as_yoctonear().to_string(),
-                    "treasury": self.etrap_settings.etrap_treasury.to_string()
-                }
-            }]
+            "data": entries
         });
         
         env::log_str(&format!("EVENT_JSON:{}", event_data));
-        
-        token
     }
     
-    // Verify a transaction belongs to a batch
-    pub fn verify_document_in_batch(
-        &self,
-        token_id: TokenId,
-        document_hash: String,
-        merkle_proof: Vec<String>,
-        leaf_index: u32,
-    ) -> bool {
-        let batch_summary = match self.batch_summaries.get(&token_id) {
-            Some(summary) => summary,
-            None => {
-                env::log_str(&format!("Batch not found: {}", token_id));
-                return false;
-            }
-        };
-        
-        // Check if the merkle root uses simple concatenation (for backward compatibility)
-        if batch_summary.merkle_root.starts_with("simple_concat:") {
-            // Use simple concatenation for testing
-            let expected_root = &batch_summary.merkle_root[14..]; // Skip "simple_concat:"
-            let mut current_hash = document_hash;
-            let mut current_index = leaf_index;
-            
-            for proof_element in merkle_proof {
-                if current_index % 2 == 0 {
-                    current_hash = format!("{}{}", current_hash, proof_element);
-                } else {
-                    current_hash = format!("{}{}", proof_element, current_hash);
-                }
-                current_index /= 2;
-            }
-            
-            return current_hash == expected_root;
+    fn validate_batch_summary(batch_summary: &BatchSummary) {
+        if let Some(leaf_count) = batch_summary.leaf_count {
+            require!(leaf_count > 0, "Batch must have at least one leaf");
+            require!(
+                leaf_count == batch_summary.tx_count,
+                format!("tx_count {} does not match leaf_count {}", batch_summary.tx_count, leaf_count)
+            );
         }
         
-        // For production: proper merkle verification with SHA256 hashing
-        // Check the hash algorithm from batch metadata
-        let use_sha256 = batch_summary.merkle_root.len() == 64 && 
-                        batch_summary.merkle_root.chars().all(|c| c.is_ascii_hexdigit());
+        if batch_summary.tree_version.is_byte_level() {
+            require!(
+                merkle::digest_from_hex(&batch_summary.merkle_root).is_some(),
+                "Merkle root must be a 32-byte hex digest for this tree version"
+            );
+            require!(
+                batch_summary.effective_hash_algorithm().is_digest(),
+                "This tree version requires the sha256 or keccak256 hash algorithm"
+            );
+        }
         
-        if use_sha256 {
-            // Proper SHA256 merkle verification
-            let mut current_hash = Self::normalize_hash(&document_hash);
-            let mut current_index = leaf_index;
-            
-            // If document_hash is not already a hash, hash it first
-            if current_hash.len() != 64 {
-                let hash_bytes = env::sha256(current_hash.as_bytes());
-                current_hash = Self::bytes_to_hex(&hash_bytes);
-            }
-            
-            for proof_element in merkle_proof.iter() {
-                let sibling_hash = Self::normalize_hash(proof_element);
-                
-                // Determine if we're the left or right sibling
-                let combined = if current_index % 2 == 0 {
-                    // We're on the left, proof element is on the right
-                    format!("{}{}", current_hash, sibling_hash)
-                } else {
-                    // We're on the right, proof element is on the left
-                    format!("{}{}", sibling_hash, current_hash)
-                };
-                
-                // Hash the combined value
-                let hash_bytes = env::sha256(combined.as_bytes());
-                current_hash = Self::bytes_to_hex(&hash_bytes);
-                
-                current_index /= 2;
-            }
-            
-            // Compare with the stored merkle root
-            let is_valid = current_hash == Self::normalize_hash(&batch_summary.merkle_root);
-            
-            env::log_str(&format!(
-                "SHA256 verification - Expected: {}, Got: {}, Valid: {}", 
-                batch_summary.merkle_root, current_hash, is_valid
-            ));
-            
-            is_valid
-        } else {
-            // Fall back to simple concatenation for non-SHA256 roots
-            let mut current_hash = document_hash;
-            let mut current_index = leaf_index;
-            
-            for proof_element in merkle_proof {
-                if current_index % 2 == 0 {
-                    current_hash = format!("{}{}", current_hash, proof_element);
-                } else {
-                    current_hash = format!("{}{}", proof_element, current_hash);
-                }
-                current_index /= 2;
+        // Only batches stored before it was required may lack the algorithm
+        let hash_algorithm = batch_summary.hash_algorithm
+            .unwrap_or_else(|| env::panic_str("hash_algorithm is required"));
+        match hash_algorithm {
+            HashAlgorithm::Sha256 | HashAlgorithm::Keccak256 => {
+                require!(
+                    merkle::digest_from_hex(&batch_summary.merkle_root).is_some(),
+                    "Merkle root must be a 32-byte hex digest for this hash algorithm"
+                );
+                // Proofs compute lowercase roots, which an uppercase root would never match
+                require!(
+                    batch_summary.merkle_root == batch_summary.merkle_root.to_lowercase(),
+                    "Merkle root must be lowercase hex"
+                );
             }
-            
-            let is_valid = current_hash == batch_summary.merkle_root;
-            
-            env::log_str(&format!(
-                "Simple verification - Expected: {}, Got: {}, Valid: {}", 
-                batch_summary.merkle_root, current_hash, is_valid
-            ));
-            
-            is_valid
+            HashAlgorithm::SimpleConcat => require!(
+                batch_summary.merkle_root.starts_with(merkle::SIMPLE_CONCAT_PREFIX),
+                "simple_concat merkle root must start with \"simple_concat:\""
+            ),
+            HashAlgorithm::Concat => {}
+        }
+        
+        if let Some(state_root) = &batch_summary.state_root {
+            require!(
+                merkle::digest_from_hex(state_root).is_some(),
+                "State root must be a 32-byte hex digest"
+            );
         }
     }
     
-    // Additional view method: compute merkle root for a set of transaction hashes
-    pub fn compute_merkle_root(&self, transaction_hashes: Vec<String>, use_sha256: bool) -> String {
-        if transaction_hashes.is_empty() {
-            return String::new();
-        }
-        
-        // Initialize current level with transaction hashes
-        let mut current_level: Vec<String> = if use_sha256 {
-            // Hash each transaction if using SHA256
-            transaction_hashes.iter()
-                .map(|tx| {
-                    let normalized = Self::normalize_hash(tx);
-                    if normalized.len() == 64 && normalized.chars().all(|c| c.is_ascii_hexdigit()) {
-                        // Already a hash
-                        normalized
-                    } else {
-                        // Hash the transaction
-                        let hash_bytes = env::sha256(tx.as_bytes());
-                        Self::bytes_to_hex(&hash_bytes)
-                    }
-                })
-                .collect()
-        } else {
-            // Use transactions as-is for simple concatenation
-            transaction_hashes
+    // Hash algorithm for the view helpers: an explicit algorithm wins over
+    // `use_sha256`, and byte-level trees can only use digests
+    fn view_hash_algorithm(
+        use_sha256: bool,
+        tree_version: TreeVersion,
+        hash_algorithm: Option<HashAlgorithm>,
+    ) -> HashAlgorithm {
+        let algorithm = match hash_algorithm {
+            Some(algorithm) => algorithm,
+            None if use_sha256 || tree_version.is_byte_level() => HashAlgorithm::Sha256,
+            None => HashAlgorithm::Concat,
         };
-        
-        // Build the tree level by level
-        while current_level.len() > 1 {
-            let mut next_level = Vec::new();
-            
-            for i in (0..current_level.len()).step_by(2) {
-                if i + 1 < current_level.len() {
-                    // Combine pair of nodes
-                    let combined = if use_sha256 {
-                        let concat = format!("{}{}", current_level[i], current_level[i + 1]);
-                        let hash_bytes = env::sha256(concat.as_bytes());
-                        Self::bytes_to_hex(&hash_bytes)
-                    } else {
-                        format!("{}{}", current_level[i], current_level[i + 1])
-                    };
-                    next_level.push(combined);
-                } else {
-                    // Odd number of nodes, promote the last one
-                    next_level.push(current_level[i].clone());
-                }
-            }
-            
-            current_level = next_level;
+        if tree_version.is_byte_level() {
+            require!(
+                algorithm.is_digest(),
+                "This tree version requires the sha256 or keccak256 hash algorithm"
+            );
         }
-        
-        current_level[0].clone()
+        algorithm
+    }
+    
+    // Index and verification view of a MerkleTreeBatchSummary. The summary
+    // carries no table names or size, so those indices and the size fee don't
+    // apply; the batch is indexed by its end timestamp.
+    fn batch_summary_from_merkle(merkle_summary: &MerkleTreeBatchSummary) -> BatchSummary {
+        let info = &merkle_summary.batch_summary;
+        require!(
+            info.start_timestamp <= info.end_timestamp,
+            "start_timestamp must not be after end_timestamp"
+        );
+        let hash_algorithm = HashAlgorithm::from_name(&merkle_summary.hash_algorithm)
+            .unwrap_or_else(|| env::panic_str(&format!(
+                "Unsupported hash algorithm: {}", merkle_summary.hash_algorithm
+            )));
+        
+        BatchSummary {
+            database_name: merkle_summary.database_name.clone(),
+            table_names: vec![],
+            timestamp: info.end_timestamp,
+            tx_count: info.total_transactions,
+            merkle_root: merkle_summary.merkle_root.clone(),
+            s3_bucket: merkle_summary.s3_bucket.clone(),
+            s3_key: merkle_summary.s3_prefix.clone(),
+            size_bytes: 0,
+            operation_counts: OperationCounts {
+                inserts: info.operations_summary.inserts,
+                updates: info.operations_summary.updates,
+                deletes: info.operations_summary.deletes,
+            },
+            tree_version: merkle_summary.tree_version,
+            hash_algorithm: Some(hash_algorithm),
+            leaf_count: Some(merkle_summary.leaf_count),
+            previous_batch_id: merkle_summary.previous_batch_id.clone(),
+            previous_root: merkle_summary.previous_root.clone(),
+            sorted_leaves: merkle_summary.sorted_leaves,
+            state_root: merkle_summary.state_root.clone(),
+        }
+    }
+    
+    // Leaf count to check a proof against: the batch's recorded one, which a
+    // caller-supplied count must agree with. Err when they disagree.
+    fn proof_leaf_count(batch_summary: &BatchSummary, leaf_count: Option<u32>) -> Result<Option<u32>, String> {
+        match (batch_summary.leaf_count, leaf_count) {
+            (Some(stored), Some(given)) if stored != given => Err(format!(
+                "Invalid proof: batch has {} leaves, not {}", stored, given
+            )),
+            (stored, given) => Ok(stored.or(given)),
+        }
+    }
+    
+    // Decode a proof element into a tree node: a 32-byte digest (hex or base64)
+    // for byte-level trees, a hex string or raw text for legacy trees
+    fn proof_node(version: TreeVersion, algorithm: HashAlgorithm, element: &str) -> Option<Vec<u8>> {
+        if version.is_byte_level() {
+            merkle::digest_from_str(element)
+        } else if algorithm.is_digest() {
+            Some(normalize_hash(element).into_bytes())
+        } else {
+            Some(element.as_bytes().to_vec())
+        }
+    }
+    
+    // Display form of a tree node, the inverse of proof_node
+    fn node_to_string(version: TreeVersion, node: &[u8]) -> String {
+        if version.is_byte_level() {
+            bytes_to_hex(node)
+        } else {
+            String::from_utf8_lossy(node).into_owned()
+        }
+    }
+    
+    // Leaf of a document in verify_document_in_batch under the legacy
+    // encoding. Legacy digest trees hash any document that isn't 64
+    // characters long.
+    fn document_leaf(version: TreeVersion, algorithm: HashAlgorithm, document_hash: &str) -> Vec<u8> {
+        if version.is_byte_level() || !algorithm.is_digest() {
+            return merkle::leaf_hash(version, algorithm, document_hash);
+        }
+        let normalized = normalize_hash(document_hash);
+        if normalized.len() == 64 {
+            normalized.into_bytes()
+        } else {
+            bytes_to_hex(&algorithm.digest(normalized.as_bytes())).into_bytes()
+        }
+    }
+    
+    // Root a single-leaf proof leads to, before comparing it with the batch's
+    fn proof_root(
+        batch_summary: &BatchSummary,
+        document_hash: &str,
+        merkle_proof: &[String],
+        leaf_index: u32,
+        leaf_count: Option<u32>,
+        leaf_encoding: LeafEncoding,
+    ) -> Result<Vec<u8>, VerificationFailure> {
+        let leaf_count = Self::proof_leaf_count(batch_summary, leaf_count)
+            .map_err(|_| VerificationFailure::LeafCountMismatch)?;
+        if leaf_count.is_some_and(|leaf_count| leaf_index >= leaf_count) {
+            return Err(VerificationFailure::IndexOutOfRange);
+        }
+        
+        // Side of each sibling in the proof
+        let sides = merkle::sibling_sides(leaf_index, leaf_count, merkle_proof.len())
+            .ok_or(VerificationFailure::BadProofLength)?;
+        
+        let version = batch_summary.tree_version;
+        let algorithm = batch_summary.effective_hash_algorithm();
+        let proof = merkle_proof.iter()
+            .map(|element| Self::proof_node(version, algorithm, element))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(VerificationFailure::InvalidProofElement)?;
+        
+        let leaf = match leaf_encoding {
+            LeafEncoding::Legacy => Self::document_leaf(version, algorithm, document_hash),
+            encoding => merkle::encoded_leaf_hash(version, algorithm, encoding, document_hash)
+                .ok_or(VerificationFailure::InvalidDocument)?,
+        };
+        Ok(merkle::root_from_proof(leaf, &proof, &sides, |left, right| {
+            merkle::node_hash(version, algorithm, left, right)
+        }))
+    }
+    
+    // A computed root in the form of the batch's merkle root
+    fn display_root(batch_summary: &BatchSummary, computed_root: &[u8]) -> String {
+        let computed_root = Self::node_to_string(batch_summary.tree_version, computed_root);
+        if batch_summary.effective_hash_algorithm() == HashAlgorithm::SimpleConcat {
+            format!("{}{}", merkle::SIMPLE_CONCAT_PREFIX, computed_root)
+        } else {
+            computed_root
+        }
+    }
+    
+    fn root_matches(batch_summary: &BatchSummary, computed_root: &[u8]) -> bool {
+        let version = batch_summary.tree_version;
+        let computed_root = Self::node_to_string(version, computed_root);
+        match batch_summary.effective_hash_algorithm() {
+            _ if version.is_byte_level() => {
+                computed_root == normalize_hash(&batch_summary.merkle_root).to_lowercase()
+            }
+            HashAlgorithm::Sha256 | HashAlgorithm::Keccak256 => {
+                computed_root == normalize_hash(&batch_summary.merkle_root)
+            }
+            HashAlgorithm::SimpleConcat => {
+                computed_root == batch_summary.merkle_root[merkle::SIMPLE_CONCAT_PREFIX.len()..]
+            }
+            HashAlgorithm::Concat => computed_root == batch_summary.merkle_root,
+        }
+    }
+    
+    fn tree_levels(
+        version: TreeVersion,
+        algorithm: HashAlgorithm,
+        transactions: &[String],
+    ) -> Vec<Vec<Vec<u8>>> {
+        let leaves = transactions.iter()
+            .map(|tx| merkle::leaf_hash(version, algorithm, tx))
+            .collect();
+        merkle::build_levels(leaves, |left, right| {
+            merkle::node_hash(version, algorithm, left, right)
+        })
+    }
+    
+    // Tree walked by both proof generators, so single proofs and multiproofs
+    // of the same documents share a root. With the legacy encoding, legacy
+    // digest trees hash every document, 64-character digests included, as
+    // generate_merkle_proof always has. None if a document doesn't decode.
+    fn proof_tree_levels(
+        version: TreeVersion,
+        algorithm: HashAlgorithm,
+        leaf_encoding: Option<LeafEncoding>,
+        transactions: &[String],
+    ) -> Option<Vec<Vec<Vec<u8>>>> {
+        match leaf_encoding.unwrap_or_default() {
+            LeafEncoding::Legacy if version == TreeVersion::Legacy && algorithm.is_digest() => {
+                let leaves = transactions.iter()
+                    .map(|tx| bytes_to_hex(&algorithm.digest(tx.as_bytes())).into_bytes())
+                    .collect();
+                Some(merkle::build_levels(leaves, |left, right| {
+                    merkle::node_hash(version, algorithm, left, right)
+                }))
+            }
+            LeafEncoding::Legacy => Some(Self::tree_levels(version, algorithm, transactions)),
+            encoding => Self::encoded_tree_levels(version, algorithm, encoding, transactions),
+        }
+    }
+    
+    // Tree levels over documents in an explicit encoding; None if one of
+    // them doesn't decode
+    fn encoded_tree_levels(
+        version: TreeVersion,
+        algorithm: HashAlgorithm,
+        encoding: LeafEncoding,
+        transactions: &[String],
+    ) -> Option<Vec<Vec<Vec<u8>>>> {
+        let leaves = transactions.iter()
+            .map(|tx| merkle::encoded_leaf_hash(version, algorithm, encoding, tx))
+            .collect::<Option<Vec<_>>>()?;
+        Some(merkle::build_levels(leaves, |left, right| {
+            merkle::node_hash(version, algorithm, left, right)
+        }))
+    }
+    
+    // Sorted (key, leaf) pairs of a sparse Merkle tree; None for an invalid
+    // row hash or a row given twice
+    fn state_leaves(rows: &[RowState]) -> Option<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut leaves = rows.iter()
+            .map(|row| {
+                let key = smt::row_key(&row.table_name, &row.primary_key_hash);
+                let row_hash = merkle::digest_from_hex(&row.row_hash)?;
+                let leaf = smt::leaf_hash(&key, &row_hash);
+                Some((key, leaf))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        leaves.sort();
+        if leaves.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return None;
+        }
+        Some(leaves)
+    }
+    
+    // A batch must name its database's current head as predecessor, or
+    // nothing if it is the database's first batch
+    fn assert_links_to_head(&self, batch_summary: &BatchSummary) {
+        match self.database_heads.get(&batch_summary.database_name) {
+            Some(head) => {
+                require!(
+                    batch_summary.previous_batch_id.as_ref() == Some(head),
+                    format!("previous_batch_id must be the database's current head: {}", head)
+                );
+                let head_root = self.internal_batch_summary(head).unwrap().merkle_root;
+                require!(
+                    batch_summary.previous_root.as_ref() == Some(&head_root),
+                    "previous_root does not match the merkle root of the database's current head"
+                );
+            }
+            None => require!(
+                batch_summary.previous_batch_id.is_none() && batch_summary.previous_root.is_none(),
+                "First batch of a database cannot have a previous batch"
+            ),
+        }
+    }
+    
+    fn decode_hex_digests(hashes: &[String]) -> Option<Vec<Vec<u8>>> {
+        hashes.iter().map(|hash| merkle::digest_from_hex(hash)).collect()
+    }
+    
+    // Nodes are keyed by the database hash, as the per-database indices are
+    fn mmr_key(database: &str, level: u8, index: u64) -> (Vec<u8>, u8, u64) {
+        (env::sha256(database.as_bytes()), level, index)
+    }
+    
+    fn mmr_node(&self, database: &str, level: u8, index: u64) -> Vec<u8> {
+        self.mmr_nodes.get(&Self::mmr_key(database, level, index))
+            .cloned()
+            .unwrap_or_else(|| env::panic_str("Missing MMR node"))
+    }
+    
+    fn mmr_peaks(&self, database: &str, leaf_count: u64) -> Vec<Vec<u8>> {
+        mmr::peaks(leaf_count, |level, index| self.mmr_node(database, level, index))
+    }
+    
+    fn internal_mmr_append(&mut self, database: &str, token_id: &TokenId, merkle_root: &str) {
+        let leaf_count = self.mmr_leaf_counts.get(database).copied().unwrap_or(0);
+        let leaf = mmr::leaf_hash(token_id, merkle_root);
+        let nodes = mmr::append(leaf_count, leaf, |level, index| {
+            self.mmr_node(database, level, index)
+        });
+        
+        for (level, index, hash) in nodes {
+            self.mmr_nodes.insert(Self::mmr_key(database, level, index), hash);
+        }
+        self.mmr_leaf_counts.insert(database.to_string(), leaf_count + 1);
+        self.mmr_leaf_indices.insert(token_id.clone(), leaf_count);
+    }
+    
+    fn internal_mint_with_indices(
+        &mut self,
+        token_id: TokenId,
+        receiver_id: AccountId,
+        token_metadata: TokenMetadata,
+        batch_summary: BatchSummary,
+    ) -> Token {
+        let mut batch_summary = batch_summary;
+        batch_summary.leaf_count.get_or_insert(batch_summary.tx_count);
+        Self::validate_batch_summary(&batch_summary);
+        self.assert_links_to_head(&batch_summary);
+        
+        // Extract searchable components
+        let database = batch_summary.database_name.clone();
+        let timestamp = batch_summary.timestamp;
+        let year_month = Self::timestamp_to_year_month(timestamp);
+        
+        // Mint the NFT
+        let token = self.tokens.internal_mint_with_refund(
+            token_id.clone(),
+            receiver_id.clone(),
+            Some(token_metadata),
+            None,
+        );
+        
+        // Update all indices atomically. The nested collections are flushed
+        // as they are updated, since their outer maps only store prefixes.
+        
+        // Index by database  
+        let mut db_tokens = self.tokens_by_database.remove(&database)
+            .unwrap_or_else(|| IterableSet::new(
+                StorageKey::TokensByDatabaseInner {
+                    database_hash: env::sha256(database.as_bytes())
+                }
+            ));
+        db_tokens.insert(token_id.clone());
+        db_tokens.flush();
+        self.tokens_by_database.insert(database.clone(), db_tokens);
+        
+        // Index by month
+        let mut month_tokens = self.tokens_by_month.remove(&year_month)
+            .unwrap_or_else(|| Vector::new(
+                StorageKey::TokensByMonthInner {
+                    month_hash: env::sha256(year_month.as_bytes())
+                }
+            ));
+        month_tokens.push(token_id.clone());
+        month_tokens.flush();
+        self.tokens_by_month.insert(year_month.clone(), month_tokens);
+        
+        // Index by timestamp
+        self.tokens_by_timestamp.insert(timestamp, token_id.clone());
+        
+        // Index by tables
+        for table in &batch_summary.table_names {
+            let mut table_tokens = self.tokens_by_table.remove(table)
+                .unwrap_or_else(|| IterableSet::new(
+                    StorageKey::TokensByTableInner {
+                        table_hash: env::sha256(table.as_bytes())
+                    }
+                ));
+            table_tokens.insert(token_id.clone());
+            table_tokens.flush();
+            self.tokens_by_table.insert(table.clone(), table_tokens);
+        }
+        
+        // Update recent tokens cache (FIFO - First In First Out)
+        self.recent_tokens.push(token_id.clone());
+        if self.recent_tokens.len() > RECENT_TOKENS_LIMIT as u32 {
+            // Remove the oldest token (first element) while maintaining order
+            // Note: This is O(n) but necessary for maintaining chronological order
+            let mut new_recent = Vector::new(StorageKey::RecentTokens);
+            for i in 1..self.recent_tokens.len() {
+                new_recent.push(self.recent_tokens.get(i).unwrap().clone());
+            }
+            self.recent_tokens = new_recent;
+        }
+        
+        // Append to the database's history
+        self.internal_mmr_append(&database, &token_id, &batch_summary.merkle_root);
+        self.database_heads.insert(database.clone(), token_id.clone());
+        if batch_summary.state_root.is_some() {
+            for table in &batch_summary.table_names {
+                self.table_state_roots.insert((database.clone(), table.clone()), token_id.clone());
+            }
+        }
+        
+        // Store batch summary and the block anchoring it
+        self.batch_summaries.insert(token_id.clone(), batch_summary.into());
+        self.batch_anchors.insert(token_id.clone(), BatchAnchor {
+            block_timestamp: env::block_timestamp(),
+            block_height: env::block_height(),
+        });
+        
+        // Update statistics
+        let count = self.total_batches_per_database.get(&database).copied().unwrap_or(0) + 1;
+        self.total_batches_per_database.insert(database.clone(), count);
+        self.database_list.insert(database);
+        
+        let billing_month = Self::current_billing_month();
+        let monthly_count = self.monthly_mint_counts.get(&billing_month).copied().unwrap_or(0) + 1;
+        self.monthly_mint_counts.insert(billing_month, monthly_count);
+        
+        token
+    }
+}
+
+#[near_bindgen]
+impl ETRAPContract {
+    // Initialize with the fee in NEAR, given as a number or a decimal string
+    #[init]
+    pub fn new(
+        organization_id: AccountId,
+        organization_name: String,
+        etrap_treasury: AccountId,
+        etrap_fee_amount: NearAmount,
+    ) -> Self {
+        // Convert NEAR to yoctoNEAR
+        let fee_amount_yocto = Self::near_amount_to_yocto(&etrap_fee_amount);
+        
+        Self::new_with_yocto_fee(
+            organization_id,
+            organization_name,
+            etrap_treasury,
+            U128(fee_amount_yocto),
+        )
+    }
+    
+    // Initialize with the fee in yoctoNEAR
+    #[init]
+    pub fn new_with_yocto_fee(
+        organization_id: AccountId,
+        organization_name: String,
+        etrap_treasury: AccountId,
+        etrap_fee_yocto: U128,
+    ) -> Self {
+        require!(!env::state_exists(), "Already initialized");
+        
+        let fee_amount = NearToken::from_yoctonear(etrap_fee_yocto.0);
+        
+        let metadata = NFTContractMetadata {
+            spec: NFT_METADATA_SPEC.to_string(),
+            name: format!("ETRAP Document Integrity Certificates - {}", organization_name),
+            symbol: format!("ETRAP-{}", organization_name.chars().take(4).collect::<String>().to_uppercase()),
+            icon: Some(DATA_IMAGE_SVG_ETRAP_ICON.to_string()),
+            base_uri: None,
+            reference: Some("https://etrap.io/contracts/nft".to_string()),
+            reference_hash: None,
+        };
+        
+        let mut fee_history = Vector::new(StorageKey::FeeHistory);
+        fee_history.push(Self::fee_record(fee_amount, UsagePricing::default(), vec![]));
+        
+        Self {
+            tokens: NonFungibleToken::new(
+                StorageKey::NonFungibleToken,
+                organization_id.clone(),
+                Some(StorageKey::TokenMetadata),
+                Some(StorageKey::Enumeration),
+                Some(StorageKey::Approval),
+            ),
+            metadata,
+            batch_summaries: LookupMap::new(StorageKey::VersionedBatchSummaries),
+            legacy_batch_summaries: LookupMap::new(StorageKey::BatchSummaries),
+            tokens_by_database: LookupMap::new(StorageKey::TokensByDatabase),
+            tokens_by_month: LookupMap::new(StorageKey::TokensByMonth),
+            tokens_by_timestamp: IterableMap::new(StorageKey::TokensByTimestamp),
+            recent_tokens: Vector::new(StorageKey::RecentTokens),
+            tokens_by_table: LookupMap::new(StorageKey::TokensByTable),
+            total_batches_per_database: LookupMap::new(StorageKey::TotalBatchesPerDatabase),
+            database_list: IterableSet::new(StorageKey::DatabaseList),
+            etrap_settings: ETRAPSettings {
+                fee_amount,
+                pricing: UsagePricing::default(),
+                discount_tiers: vec![],
+                fee_recipients: Self::single_fee_recipient(etrap_treasury),
+                paused: false,
+            },
+            owner_id: Some(organization_id),
+            pending_owner_id: None,
+            role_members: LookupMap::new(StorageKey::RoleMembers),
+            approval_config: ApprovalConfig {
+                approvers: vec![],
+                threshold: 0,
+                proposal_duration_ns: DEFAULT_PROPOSAL_DURATION_NS,
+            },
+            admin_proposals: IterableMap::new(StorageKey::AdminProposals),
+            next_proposal_id: 0,
+            storage_balances: LookupMap::new(StorageKey::StorageBalances),
+            fee_history,
+            monthly_mint_counts: LookupMap::new(StorageKey::MonthlyMintCounts),
+            fee_balances: LookupMap::new(StorageKey::FeeBalances),
+            fee_totals: FeeBalance::default(),
+            merkle_summaries: LookupMap::new(StorageKey::MerkleSummaries),
+            mmr_nodes: LookupMap::new(StorageKey::MmrNodes),
+            mmr_leaf_counts: LookupMap::new(StorageKey::MmrLeafCounts),
+            mmr_leaf_indices: LookupMap::new(StorageKey::MmrLeafIndices),
+            database_heads: LookupMap::new(StorageKey::DatabaseHeads),
+            table_state_roots: LookupMap::new(StorageKey::TableStateRoots),
+            batch_anchors: LookupMap::new(StorageKey::BatchAnchors),
+        }
+    }
+    
+    // Upgrade state written by the original contract. Existing batches and
+    // indices keep their storage; the owner of the NFT contract becomes the
+    // owner, the treasury the sole fee recipient, and everything added since
+    // starts empty.
+    #[init(ignore_state)]
+    #[private]
+    pub fn migrate() -> Self {
+        let old: ETRAPContractV0 = env::state_read()
+            .unwrap_or_else(|| env::panic_str("No state to migrate"));
+        let owner_id = old.tokens.owner_id.clone();
+        let fee_amount = old.etrap_settings.fee_amount;
+        
+        let mut fee_history = Vector::new(StorageKey::FeeHistory);
+        fee_history.push(Self::fee_record(fee_amount, UsagePricing::default(), vec![]));
+        
+        Self {
+            tokens: old.tokens,
+            metadata: old.metadata,
+            batch_summaries: LookupMap::new(StorageKey::VersionedBatchSummaries),
+            legacy_batch_summaries: old.batch_summaries,
+            tokens_by_database: old.tokens_by_database,
+            tokens_by_month: old.tokens_by_month,
+            tokens_by_timestamp: old.tokens_by_timestamp,
+            recent_tokens: old.recent_tokens,
+            tokens_by_table: old.tokens_by_table,
+            total_batches_per_database: old.total_batches_per_database,
+            database_list: old.database_list,
+            etrap_settings: ETRAPSettings {
+                fee_amount,
+                pricing: UsagePricing::default(),
+                discount_tiers: vec![],
+                fee_recipients: Self::single_fee_recipient(old.etrap_settings.etrap_treasury),
+                paused: old.etrap_settings.paused,
+            },
+            owner_id: Some(owner_id),
+            pending_owner_id: None,
+            role_members: LookupMap::new(StorageKey::RoleMembers),
+            approval_config: ApprovalConfig {
+                approvers: vec![],
+                threshold: 0,
+                proposal_duration_ns: DEFAULT_PROPOSAL_DURATION_NS,
+            },
+            admin_proposals: IterableMap::new(StorageKey::AdminProposals),
+            next_proposal_id: 0,
+            storage_balances: LookupMap::new(StorageKey::StorageBalances),
+            fee_history,
+            monthly_mint_counts: LookupMap::new(StorageKey::MonthlyMintCounts),
+            fee_balances: LookupMap::new(StorageKey::FeeBalances),
+            fee_totals: FeeBalance::default(),
+            merkle_summaries: LookupMap::new(StorageKey::MerkleSummaries),
+            mmr_nodes: LookupMap::new(StorageKey::MmrNodes),
+            mmr_leaf_counts: LookupMap::new(StorageKey::MmrLeafCounts),
+            mmr_leaf_indices: LookupMap::new(StorageKey::MmrLeafIndices),
+            database_heads: LookupMap::new(StorageKey::DatabaseHeads),
+            table_state_roots: LookupMap::new(StorageKey::TableStateRoots),
+            batch_anchors: LookupMap::new(StorageKey::BatchAnchors),
+        }
+    }
+    
+    // Mint a new batch certificate NFT
+    #[payable]
+    pub fn mint_batch(
+        &mut self,
+        token_id: TokenId,
+        receiver_id: AccountId,
+        token_metadata: TokenMetadata,
+        batch_summary: BatchSummary,
+    ) -> Token {
+        self.assert_can_mint();
+        
+        // Validate token doesn't already exist
+        require!(
+            self.tokens.nft_token(token_id.clone()).is_none(),
+            "Token already exists"
+        );
+        
+        // ETRAP fee depends on the batch's transaction count and size
+        let fee = self.internal_compute_fee(&batch_summary);
+        let etrap_fee = fee.total_fee;
+        let initial_storage_usage = env::storage_usage();
+        
+        // Mint with indices
+        let token = self.internal_mint_with_indices(
+            token_id.clone(),
+            receiver_id.clone(),
+            token_metadata,
+            batch_summary.clone(),
+        );
+        
+        // Escrow fee for the fee recipients to withdraw
+        self.accrue_fees(std::slice::from_ref(&fee));
+        
+        // Charge the exact storage used plus the fee, refund the rest
+        self.settle_mint_deposit(initial_storage_usage, etrap_fee);
+        
+        // Emit detailed event for off-chain indexers
+        Self::emit_mint_event(vec![
+            self.mint_event_entry(&receiver_id, &token_id, &batch_summary, &fee),
+        ]);
+        
+        token
+    }
+    
+    // Mint several batch certificates in one transaction. Either every batch is
+    // minted or the whole call fails. The fees are escrowed for the fee
+    // recipients and one mint event lists all minted tokens.
+    #[payable]
+    pub fn mint_batches(&mut self, batches: Vec<MintArgs>) -> Vec<Token> {
+        self.assert_can_mint();
+        
+        require!(!batches.is_empty(), "No batches to mint");
+        require!(
+            batches.len() <= MAX_BATCHES_PER_MINT,
+            format!("Cannot mint more than {} batches at once", MAX_BATCHES_PER_MINT)
+        );
+        
+        let mut etrap_fee_total = NearToken::from_yoctonear(0);
+        let mut fees = Vec::with_capacity(batches.len());
+        let initial_storage_usage = env::storage_usage();
+        
+        let mut tokens = Vec::with_capacity(batches.len());
+        let mut event_entries = Vec::with_capacity(batches.len());
+        
+        for args in batches {
+            require!(
+                self.tokens.nft_token(args.token_id.clone()).is_none(),
+                format!("Token already exists: {}", args.token_id)
+            );
+            
+            // Priced before minting so each batch counts towards the next one's tier
+            let fee = self.internal_compute_fee(&args.batch_summary);
+            etrap_fee_total = etrap_fee_total.saturating_add(fee.total_fee);
+            
+            let token = self.internal_mint_with_indices(
+                args.token_id.clone(),
+                args.receiver_id.clone(),
+                args.token_metadata,
+                args.batch_summary.clone(),
+            );
+            
+            event_entries.push(
+                self.mint_event_entry(&args.receiver_id, &args.token_id, &args.batch_summary, &fee)
+            );
+            fees.push(fee);
+            tokens.push(token);
+        }
+        
+        // Escrow the fees for the fee recipients to withdraw
+        self.accrue_fees(&fees);
+        
+        self.settle_mint_deposit(initial_storage_usage, etrap_fee_total);
+        
+        Self::emit_mint_event(event_entries);
+        
+        tokens
+    }
+    
+    // Mint a batch from a full MerkleTreeBatchSummary. The summary is stored
+    // as given, except that the anchoring data is filled in by the contract.
+    #[payable]
+    pub fn mint_merkle_batch(
+        &mut self,
+        token_id: TokenId,
+        receiver_id: AccountId,
+        token_metadata: TokenMetadata,
+        merkle_summary: MerkleTreeBatchSummary,
+    ) -> Token {
+        self.assert_can_mint();
+        
+        require!(
+            self.tokens.nft_token(token_id.clone()).is_none(),
+            "Token already exists"
+        );
+        
+        require!(merkle_summary.batch_id == token_id, "batch_id must equal token_id");
+        
+        let batch_summary = Self::batch_summary_from_merkle(&merkle_summary);
+        let fee = self.internal_compute_fee(&batch_summary);
+        let etrap_fee = fee.total_fee;
+        let initial_storage_usage = env::storage_usage();
+        
+        let token = self.internal_mint_with_indices(
+            token_id.clone(),
+            receiver_id.clone(),
+            token_metadata,
+            batch_summary.clone(),
+        );
+        
+        let mut merkle_summary = merkle_summary;
+        merkle_summary.anchoring_data.block_height = env::block_height();
+        merkle_summary.anchoring_data.gas_used = env::used_gas().as_gas().to_string();
+        merkle_summary.anchoring_data.etrap_fee = etrap_fee.as_yoctonear().to_string();
+        self.merkle_summaries.insert(token_id.clone(), merkle_summary);
+        
+        self.accrue_fees(std::slice::from_ref(&fee));
+        
+        self.settle_mint_deposit(initial_storage_usage, etrap_fee);
+        
+        Self::emit_mint_event(vec![
+            self.mint_event_entry(&receiver_id, &token_id, &batch_summary, &fee),
+        ]);
+        
+        token
+    }
+    
+    // Verify a transaction belongs to a batch. The proof is walked with the
+    // batch's leaf count, or `leaf_count` for batches minted before it was
+    // recorded; with neither the proof is walked by the bits of `leaf_index`,
+    // which fails for leaves promoted in unbalanced trees. `leaf_encoding`
+    // says how `document_hash` is given and defaults to UTF-8 text.
+    pub fn verify_document_in_batch(
+        &self,
+        token_id: TokenId,
+        document_hash: String,
+        merkle_proof: Vec<String>,
+        leaf_index: u32,
+        leaf_count: Option<u32>,
+        leaf_encoding: Option<LeafEncoding>,
+    ) -> bool {
+        let result = self.verify_document_detailed(
+            token_id.clone(), document_hash, merkle_proof, leaf_index, leaf_count, leaf_encoding,
+        );
+        
+        env::log_str(&format!(
+            "Verification of {} - Expected: {}, Got: {}, Valid: {}, Failure: {:?}",
+            token_id,
+            result.expected_root.unwrap_or_default(),
+            result.computed_root.unwrap_or_default(),
+            result.valid,
+            result.failure
+        ));
+        
+        result.valid
+    }
+    
+    // verify_document_in_batch with the reason for a failure, the computed
+    // root and the batch's scheme and anchoring, for callers that can't see logs
+    pub fn verify_document_detailed(
+        &self,
+        token_id: TokenId,
+        document_hash: String,
+        merkle_proof: Vec<String>,
+        leaf_index: u32,
+        leaf_count: Option<u32>,
+        leaf_encoding: Option<LeafEncoding>,
+    ) -> DocumentVerification {
+        let batch_summary = match self.internal_batch_summary(&token_id) {
+            Some(summary) => summary,
+            None => return DocumentVerification {
+                valid: false,
+                failure: Some(VerificationFailure::BatchNotFound),
+                computed_root: None,
+                expected_root: None,
+                tree_version: None,
+                hash_algorithm: None,
+                timestamp: None,
+                block_height: None,
+            },
+        };
+        
+        let computed_root = Self::proof_root(
+            &batch_summary,
+            &document_hash,
+            &merkle_proof,
+            leaf_index,
+            leaf_count,
+            leaf_encoding.unwrap_or_default(),
+        );
+        let failure = match &computed_root {
+            Err(failure) => Some(*failure),
+            Ok(root) if !Self::root_matches(&batch_summary, root) => Some(VerificationFailure::RootMismatch),
+            Ok(_) => None,
+        };
+        
+        let anchor = self.batch_anchors.get(&token_id);
+        DocumentVerification {
+            valid: failure.is_none(),
+            failure,
+            computed_root: computed_root.ok().map(|root| Self::display_root(&batch_summary, &root)),
+            expected_root: Some(batch_summary.merkle_root.clone()),
+            tree_version: Some(batch_summary.tree_version),
+            hash_algorithm: Some(batch_summary.effective_hash_algorithm()),
+            timestamp: anchor.map(|anchor| anchor.block_timestamp),
+            block_height: anchor.map(|anchor| anchor.block_height),
+        }
+    }
+    
+    // Verify several transactions of one batch against a single multi-proof.
+    // `leaves[i]` sits at `indices[i]`; the multi-proof lists the missing
+    // sibling hashes level by level, as returned by generate_merkle_multiproof.
+    // `leaf_count` is only needed for batches that don't record one.
+    pub fn verify_documents_in_batch(
+        &self,
+        token_id: TokenId,
+        leaves: Vec<String>,
+        indices: Vec<u32>,
+        multiproof: Vec<String>,
+        leaf_count: Option<u32>,
+        leaf_encoding: Option<LeafEncoding>,
+    ) -> bool {
+        let batch_summary = match self.internal_batch_summary(&token_id) {
+            Some(summary) => summary,
+            None => {
+                env::log_str(&format!("Batch not found: {}", token_id));
+                return false;
+            }
+        };
+        
+        if leaves.len() != indices.len() {
+            env::log_str("Invalid multi-proof: leaves and indices differ in length");
+            return false;
+        }
+        
+        let leaf_count = match Self::proof_leaf_count(&batch_summary, leaf_count) {
+            Ok(Some(leaf_count)) => leaf_count,
+            Ok(None) => {
+                env::log_str("Invalid multi-proof: leaf_count is required for this batch");
+                return false;
+            }
+            Err(message) => {
+                env::log_str(&message);
+                return false;
+            }
+        };
+        
+        let version = batch_summary.tree_version;
+        let algorithm = batch_summary.effective_hash_algorithm();
+        
+        let leaf_hashes: Option<Vec<Vec<u8>>> = leaves.iter()
+            .map(|leaf| match leaf_encoding.unwrap_or_default() {
+                LeafEncoding::Legacy => Some(merkle::leaf_hash(version, algorithm, leaf)),
+                encoding => merkle::encoded_leaf_hash(version, algorithm, encoding, leaf),
+            })
+            .collect();
+        let leaf_hashes = match leaf_hashes {
+            Some(leaf_hashes) => leaf_hashes,
+            None => {
+                env::log_str("Invalid multi-proof: a document does not decode in the leaf encoding");
+                return false;
+            }
+        };
+        
+        let mut proven: Vec<(u32, Vec<u8>)> = indices.into_iter().zip(leaf_hashes).collect();
+        proven.sort_by_key(|(index, _)| *index);
+        if proven.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            env::log_str("Invalid multi-proof: duplicate leaf index");
+            return false;
+        }
+        
+        let proof: Option<Vec<Vec<u8>>> = multiproof.iter()
+            .map(|element| Self::proof_node(version, algorithm, element))
+            .collect();
+        let proof = match proof {
+            Some(proof) => proof,
+            None => {
+                env::log_str("Invalid proof element: expected 32-byte digests in hex or base64");
+                return false;
+            }
+        };
+        
+        let computed_root = merkle::root_from_multiproof(leaf_count, proven, &proof, |left, right| {
+            merkle::node_hash(version, algorithm, left, right)
+        });
+        let computed_root = match computed_root {
+            Some(root) => root,
+            None => {
+                env::log_str("Invalid multi-proof: indices or proof length do not fit the leaf count");
+                return false;
+            }
+        };
+        
+        let is_valid = Self::root_matches(&batch_summary, &computed_root);
+        
+        env::log_str(&format!(
+            "{:?}/{:?} multi-proof verification of {} leaves - Expected: {}, Got: {}, Valid: {}", 
+            version, algorithm, leaves.len(), batch_summary.merkle_root,
+            Self::node_to_string(version, &computed_root), is_valid
+        ));
+        
+        is_valid
+    }
+    
+    // Prove that `key` is not a leaf of a batch with sorted leaves: the
+    // neighbors must be adjacent leaves with left < key < right
+    pub fn verify_non_membership(
+        &self,
+        token_id: TokenId,
+        key: String,
+        left_neighbor_proof: Option<NeighborProof>,
+        right_neighbor_proof: Option<NeighborProof>,
+        leaf_encoding: Option<LeafEncoding>,
+    ) -> bool {
+        let batch_summary = match self.internal_batch_summary(&token_id) {
+            Some(summary) => summary,
+            None => {
+                env::log_str(&format!("Batch not found: {}", token_id));
+                return false;
+            }
+        };
+        
+        let leaf_count = match batch_summary.leaf_count {
+            Some(leaf_count) if batch_summary.sorted_leaves => leaf_count,
+            _ => {
+                env::log_str("Batch does not have sorted leaves");
+                return false;
+            }
+        };
+        
+        let adjacent = match (&left_neighbor_proof, &right_neighbor_proof) {
+            (Some(left), Some(right)) => left.leaf_index.checked_add(1) == Some(right.leaf_index),
+            (Some(left), None) => left.leaf_index.checked_add(1) == Some(leaf_count),
+            (None, Some(right)) => right.leaf_index == 0,
+            (None, None) => false,
+        };
+        // Compare digests, so a raw key whose hash is a leaf is not absent
+        let algorithm = batch_summary.effective_hash_algorithm();
+        let encoding = leaf_encoding.unwrap_or_default();
+        let sort_digest = |document: &str| merkle::sort_digest(algorithm, encoding, document);
+        let key = match sort_digest(&key) {
+            Some(key) => key,
+            None => {
+                env::log_str("Key does not decode");
+                return false;
+            }
+        };
+        let bracketed = left_neighbor_proof.as_ref()
+            .is_none_or(|left| sort_digest(&left.document_hash).is_some_and(|left| left < key))
+            && right_neighbor_proof.as_ref()
+                .is_none_or(|right| sort_digest(&right.document_hash).is_some_and(|right| right > key));
+        if !adjacent || !bracketed {
+            env::log_str("Neighbors are not adjacent leaves bracketing the key");
+            return false;
+        }
+        
+        [left_neighbor_proof, right_neighbor_proof]
+            .into_iter()
+            .flatten()
+            .all(|neighbor| self.verify_document_in_batch(
+                token_id.clone(),
+                neighbor.document_hash,
+                neighbor.merkle_proof,
+                neighbor.leaf_index,
+                None,
+                Some(encoding),
+            ))
+    }
+    
+    // Additional view method: compute merkle root for a set of transaction hashes.
+    // `hash_algorithm` takes precedence over `use_sha256`; byte-level trees
+    // default to SHA256.
+    pub fn compute_merkle_root(
+        &self,
+        transaction_hashes: Vec<String>,
+        use_sha256: bool,
+        tree_version: Option<TreeVersion>,
+        hash_algorithm: Option<HashAlgorithm>,
+        leaf_encoding: Option<LeafEncoding>,
+    ) -> String {
+        if transaction_hashes.is_empty() {
+            return String::new();
+        }
+        
+        let tree_version = tree_version.unwrap_or_default();
+        let algorithm = Self::view_hash_algorithm(use_sha256, tree_version, hash_algorithm);
+        
+        let levels = match leaf_encoding.unwrap_or_default() {
+            LeafEncoding::Legacy => Self::tree_levels(tree_version, algorithm, &transaction_hashes),
+            encoding => match Self::encoded_tree_levels(tree_version, algorithm, encoding, &transaction_hashes) {
+                Some(levels) => levels,
+                None => return String::new(),
+            },
+        };
+        
+        let root = Self::node_to_string(tree_version, &levels.last().unwrap()[0]);
+        if algorithm == HashAlgorithm::SimpleConcat {
+            return format!("{}{}", merkle::SIMPLE_CONCAT_PREFIX, root);
+        }
+        root
     }
     
     // View method to help with testing: generate merkle proof for a transaction
-    pub fn generate_merkle_proof(&self, transactions: Vec<String>, tx_index: u32, use_sha256: bool) -> Vec<String> {
+    pub fn generate_merkle_proof(
+        &self,
+        transactions: Vec<String>,
+        tx_index: u32,
+        use_sha256: bool,
+        tree_version: Option<TreeVersion>,
+        hash_algorithm: Option<HashAlgorithm>,
+        leaf_encoding: Option<LeafEncoding>,
+    ) -> Vec<String> {
         if transactions.is_empty() || tx_index >= transactions.len() as u32 {
             return vec![];
         }
         
-        let mut tree: Vec<Vec<String>> = vec![];
+        let tree_version = tree_version.unwrap_or_default();
+        let algorithm = Self::view_hash_algorithm(use_sha256, tree_version, hash_algorithm);
+        
+        Self::proof_tree_levels(tree_version, algorithm, leaf_encoding, &transactions)
+            .map(|levels| {
+                merkle::generate_proof(&levels, tx_index as usize)
+                    .iter()
+                    .map(|node| Self::node_to_string(tree_version, node))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    
+    // View method: generate one multi-proof for the transactions at `indices`,
+    // for use with verify_documents_in_batch. Takes the same tree parameters
+    // as generate_merkle_proof.
+    pub fn generate_merkle_multiproof(
+        &self,
+        transactions: Vec<String>,
+        indices: Vec<u32>,
+        use_sha256: bool,
+        tree_version: Option<TreeVersion>,
+        hash_algorithm: Option<HashAlgorithm>,
+        leaf_encoding: Option<LeafEncoding>,
+    ) -> Vec<String> {
+        let mut indices = indices;
+        indices.sort_unstable();
+        indices.dedup();
+        if indices.is_empty() || indices.last().is_some_and(|&i| i as usize >= transactions.len()) {
+            return vec![];
+        }
+        
+        let tree_version = tree_version.unwrap_or_default();
+        let algorithm = Self::view_hash_algorithm(use_sha256, tree_version, hash_algorithm);
+        
+        Self::proof_tree_levels(tree_version, algorithm, leaf_encoding, &transactions)
+            .map(|levels| {
+                merkle::generate_multiproof(&levels, &indices)
+                    .iter()
+                    .map(|node| Self::node_to_string(tree_version, node))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    
+    // Sparse Merkle root of the latest row versions; null if a row hash is
+    // not a 32-byte hex digest or a row is given twice
+    pub fn compute_state_root(&self, rows: Vec<RowState>) -> Option<String> {
+        Self::state_leaves(&rows).map(|leaves| bytes_to_hex(&smt::root(&leaves)))
+    }
+    
+    // Proof of a row's state (or absence) in the tree of `rows`, for
+    // verify_row_state
+    pub fn generate_state_proof(
+        &self,
+        rows: Vec<RowState>,
+        table_name: String,
+        primary_key_hash: String,
+    ) -> Option<Vec<String>> {
+        let leaves = Self::state_leaves(&rows)?;
+        let key = smt::row_key(&table_name, &primary_key_hash);
+        Some(
+            smt::proof(&leaves, &key)
+                .iter()
+                .map(|sibling| if sibling[..] == smt::EMPTY { String::new() } else { bytes_to_hex(sibling) })
+                .collect()
+        )
+    }
+    
+    // Neighbor proofs showing that `key` is not among `transactions`, for a
+    // batch minted with sorted leaves. `transactions` are sorted here; null if
+    // the key is present.
+    pub fn generate_non_membership_proof(
+        &self,
+        transactions: Vec<String>,
+        key: String,
+        tree_version: Option<TreeVersion>,
+        hash_algorithm: Option<HashAlgorithm>,
+        leaf_encoding: Option<LeafEncoding>,
+    ) -> Option<NonMembershipProof> {
+        let tree_version = tree_version.unwrap_or_default();
+        let algorithm = Self::view_hash_algorithm(true, tree_version, hash_algorithm);
+        let encoding = leaf_encoding.unwrap_or_default();
+        let sort_digest = |document: &str| merkle::sort_digest(algorithm, encoding, document);
+        
+        let mut sorted = transactions.into_iter()
+            .map(|tx| sort_digest(&tx).map(|digest| (digest, tx)))
+            .collect::<Option<Vec<_>>>()?;
+        sorted.sort();
+        
+        let key = sort_digest(&key)?;
+        let position = match sorted.binary_search_by(|(digest, _)| digest.cmp(&key)) {
+            Ok(_) => return None,
+            Err(position) => position,
+        };
+        
+        let transactions: Vec<String> = sorted.into_iter().map(|(_, tx)| tx).collect();
+        let levels = match encoding {
+            LeafEncoding::Legacy => Self::tree_levels(tree_version, algorithm, &transactions),
+            encoding => Self::encoded_tree_levels(tree_version, algorithm, encoding, &transactions)?,
+        };
+        let neighbor = |index: usize| NeighborProof {
+            document_hash: transactions[index].clone(),
+            leaf_index: index as u32,
+            merkle_proof: merkle::generate_proof(&levels, index)
+                .iter()
+                .map(|node| Self::node_to_string(tree_version, node))
+                .collect(),
+        };
+        
+        Some(NonMembershipProof {
+            left_neighbor_proof: position.checked_sub(1).map(neighbor),
+            right_neighbor_proof: (position < transactions.len()).then(|| neighbor(position)),
+        })
+    }
+    
+    // Get recent batches
+    pub fn get_recent_batches(&self, limit: Option<u64>) -> Vec<BatchInfo> {
+        let limit = limit.unwrap_or(20).min(RECENT_TOKENS_LIMIT) as usize;
+        let start = self.recent_tokens.len().saturating_sub(limit as u32);
+        
+        (start..self.recent_tokens.len())
+            .rev()
+            .map(|i| {
+                let token_id = self.recent_tokens.get(i).unwrap();
+                self.get_batch_info(token_id)
+            })
+            .collect()
+    }
+    
+    // Search by database with pagination
+    pub fn get_batches_by_database(
+        &self,
+        database: String,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> BatchSearchResult {
+        let limit = limit.unwrap_or(50).min(100) as usize;
+        let from_index = from_index.unwrap_or(0) as usize;
+        
+        let db_tokens = match self.tokens_by_database.get(&database) {
+            Some(tokens) => tokens,
+            None => {
+                return BatchSearchResult {
+                    batches: vec![],
+                    total_count: 0,
+                    has_more: false,
+                };
+            }
+        };
+        
+        let total = db_tokens.len() as u64;
+        let tokens: Vec<BatchInfo> = db_tokens
+            .iter()
+            .skip(from_index)
+            .take(limit)
+            .map(|token_id| self.get_batch_info(token_id))
+            .collect();
+        
+        BatchSearchResult {
+            batches: tokens,
+            total_count: total,
+            has_more: from_index + limit < total as usize,
+        }
+    }
+    
+    // Time range query
+    pub fn get_batches_by_time_range(
+        &self,
+        start_timestamp: u64,
+        end_timestamp: u64,
+        database: Option<String>,
+        limit: Option<u64>,
+    ) -> Vec<BatchInfo> {
+        let limit = limit.unwrap_or(100).min(1000) as usize;
+        
+        self.tokens_by_timestamp
+            .iter()
+            .filter(|(ts, _)| **ts >= start_timestamp && **ts <= end_timestamp)
+            .filter_map(|(_, token_id)| {
+                let summary = self.internal_batch_summary(token_id)?;
+                
+                // Apply database filter if provided
+                if let Some(ref db) = database {
+                    if &summary.database_name != db {
+                        return None;
+                    }
+                }
+                
+                Some(self.get_batch_info(token_id))
+            })
+            .take(limit)
+            .collect()
+    }
+    
+    // Get batches by table
+    pub fn get_batches_by_table(
+        &self,
+        table_name: String,
+        limit: Option<u64>,
+    ) -> Vec<BatchInfo> {
+        let limit = limit.unwrap_or(50).min(100) as usize;
+        
+        self.tokens_by_table
+            .get(&table_name)
+            .map(|tokens| {
+                tokens.iter()
+                    .take(limit)
+                    .map(|token_id| self.get_batch_info(token_id))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    
+    // Get batch statistics
+    pub fn get_batch_stats(&self, token_id: Option<TokenId>) -> serde_json::Value {
+        if let Some(tid) = token_id {
+            // Stats for specific batch
+            let summary = self.internal_batch_summary(&tid)
+                .expect("Batch not found");
+            
+            json!({
+                "token_id": tid,
+                "database": summary.database_name,
+                "transaction_count": summary.tx_count,
+                "merkle_root": summary.merkle_root,
+                "timestamp": summary.timestamp,
+                "operations": {
+                    "inserts": summary.operation_counts.inserts,
+                    "updates": summary.operation_counts.updates,
+                    "deletes": summary.operation_counts.deletes,
+                }
+            })
+        } else {
+            // Global stats
+            let total_batches = self.tokens.nft_total_supply();
+            let total_databases = self.database_list.len();
+            
+            json!({
+                "total_batches": total_batches,
+                "total_databases": total_databases,
+                "databases": self.database_list.iter().cloned().collect::<Vec<_>>(),
+            })
+        }
+    }
+    
+    // Get databases list
+    pub fn get_databases(&self) -> Vec<String> {
+        self.database_list.iter().cloned().collect()
+    }
+    
+    // Peaks and root of a database's Merkle Mountain Range of batches
+    pub fn get_database_mmr(&self, database_name: String) -> Option<DatabaseMmr> {
+        let leaf_count = self.mmr_leaf_counts.get(&database_name).copied()?;
+        let peaks = self.mmr_peaks(&database_name, leaf_count);
+        let root = mmr::bag_peaks(&peaks)?;
+        
+        Some(DatabaseMmr {
+            database_name,
+            leaf_count,
+            peaks: peaks.iter().map(|peak| bytes_to_hex(peak)).collect(),
+            root: bytes_to_hex(&root),
+        })
+    }
+    
+    // Proof that a batch is part of its database's Merkle Mountain Range
+    pub fn get_mmr_inclusion_proof(&self, token_id: TokenId) -> Option<MmrInclusionProof> {
+        let leaf_index = self.mmr_leaf_indices.get(&token_id).copied()?;
+        let database_name = self.internal_batch_summary(&token_id)?.database_name.clone();
+        let leaf_count = self.mmr_leaf_counts.get(&database_name).copied()?;
+        
+        let (siblings, peak_index) = mmr::inclusion_proof(leaf_index, leaf_count, |level, index| {
+            self.mmr_node(&database_name, level, index)
+        });
+        let peaks = self.mmr_peaks(&database_name, leaf_count);
+        let root = mmr::bag_peaks(&peaks)?;
+        
+        Some(MmrInclusionProof {
+            leaf: bytes_to_hex(&self.mmr_node(&database_name, 0, leaf_index)),
+            token_id,
+            database_name,
+            leaf_index,
+            leaf_count,
+            siblings: siblings.iter().map(|sibling| bytes_to_hex(sibling)).collect(),
+            peak_index: peak_index as u32,
+            peaks: peaks.iter().map(|peak| bytes_to_hex(peak)).collect(),
+            root: bytes_to_hex(&root),
+        })
+    }
+    
+    // Proof that the database's history at `new_size` batches only appended to
+    // the history at `old_size` batches. None unless 0 < old_size <= new_size
+    // <= the current number of batches.
+    pub fn get_consistency_proof(
+        &self,
+        database: String,
+        old_size: u64,
+        new_size: u64,
+    ) -> Option<MmrConsistencyProof> {
+        let leaf_count = self.mmr_leaf_counts.get(&database).copied()?;
+        if old_size == 0 || old_size > new_size || new_size > leaf_count {
+            return None;
+        }
+        
+        let node = |level, index| self.mmr_node(&database, level, index);
+        let old_peak_paths = mmr::peak_positions(old_size)
+            .into_iter()
+            .map(|(level, index)| {
+                mmr::path_to_peak(level, index, new_size, node).0
+                    .iter()
+                    .map(|sibling| bytes_to_hex(sibling))
+                    .collect()
+            })
+            .collect();
+        
+        Some(MmrConsistencyProof {
+            old_peaks: self.mmr_peaks(&database, old_size).iter().map(|peak| bytes_to_hex(peak)).collect(),
+            old_peak_paths,
+            new_peaks: self.mmr_peaks(&database, new_size).iter().map(|peak| bytes_to_hex(peak)).collect(),
+            database_name: database,
+            old_size,
+            new_size,
+        })
+    }
+    
+    // Check two saved snapshots (size and root) of a database's history
+    // against a consistency proof. Uses only the arguments, not contract state.
+    pub fn verify_consistency(
+        &self,
+        old_size: u64,
+        old_root: String,
+        new_size: u64,
+        new_root: String,
+        proof: MmrConsistencyProof,
+    ) -> bool {
+        if proof.old_size != old_size || proof.new_size != new_size {
+            env::log_str("Invalid consistency proof: sizes do not match the snapshots");
+            return false;
+        }
+        
+        let old_peak_paths: Option<Vec<Vec<Vec<u8>>>> = proof.old_peak_paths.iter()
+            .map(|path| Self::decode_hex_digests(path))
+            .collect();
+        
+        match (
+            merkle::digest_from_hex(&old_root),
+            merkle::digest_from_hex(&new_root),
+            Self::decode_hex_digests(&proof.old_peaks),
+            old_peak_paths,
+            Self::decode_hex_digests(&proof.new_peaks),
+        ) {
+            (Some(old_root), Some(new_root), Some(old_peaks), Some(old_peak_paths), Some(new_peaks)) => {
+                mmr::verify_consistency(
+                    old_size, &old_root, new_size, &new_root, &old_peaks, &old_peak_paths, &new_peaks,
+                )
+            }
+            _ => {
+                env::log_str("Invalid consistency proof: expected 32-byte hex digests");
+                false
+            }
+        }
+    }
+    
+    // Latest batch of a database, which the next batch must link to
+    pub fn get_database_head(&self, database_name: String) -> Option<TokenId> {
+        self.database_heads.get(&database_name).cloned()
+    }
+    
+    // Walk a database's batch chain backwards from `from_token_id` (default:
+    // the head) for up to `limit` batches and report every broken link
+    pub fn verify_batch_chain(
+        &self,
+        database_name: String,
+        from_token_id: Option<TokenId>,
+        limit: Option<u64>,
+    ) -> ChainVerification {
+        let limit = limit.unwrap_or(100).min(MAX_CHAIN_WALK);
+        let mut current = from_token_id.or_else(|| self.database_heads.get(&database_name).cloned());
+        let mut batches_checked = 0;
+        let mut breaks = vec![];
+        let mut complete = false;
+        
+        while let Some(token_id) = current.take() {
+            if batches_checked == limit {
+                current = Some(token_id);
+                break;
+            }
+            
+            let summary = match self.internal_batch_summary(&token_id) {
+                Some(summary) if summary.database_name == database_name => summary,
+                _ => break,
+            };
+            batches_checked += 1;
+            
+            let leaf_index = self.mmr_leaf_indices.get(&token_id).copied();
+            let previous_batch_id = match &summary.previous_batch_id {
+                Some(previous_batch_id) => previous_batch_id,
+                None => {
+                    if leaf_index == Some(0) {
+                        complete = true;
+                    } else {
+                        breaks.push(ChainBreak { token_id, previous_batch_id: None, kind: ChainBreakKind::Unlinked });
+                    }
+                    break;
+                }
+            };
+            
+            let mut report = |kind| breaks.push(ChainBreak {
+                token_id: token_id.clone(),
+                previous_batch_id: Some(previous_batch_id.clone()),
+                kind,
+            });
+            
+            let previous = match self.internal_batch_summary(previous_batch_id) {
+                Some(previous) => previous,
+                None => {
+                    report(ChainBreakKind::PreviousNotFound);
+                    break;
+                }
+            };
+            if previous.database_name != database_name {
+                report(ChainBreakKind::DatabaseMismatch);
+                break;
+            }
+            if summary.previous_root.as_ref() != Some(&previous.merkle_root) {
+                report(ChainBreakKind::RootMismatch);
+            }
+            let previous_index = self.mmr_leaf_indices.get(previous_batch_id).copied();
+            if previous_index.zip(leaf_index).is_some_and(|(previous, current)| previous + 1 != current) {
+                report(ChainBreakKind::Gap);
+            }
+            
+            current = Some(previous_batch_id.clone());
+        }
+        
+        ChainVerification {
+            database_name,
+            batches_checked,
+            breaks,
+            next_token_id: current,
+            complete,
+        }
+    }
+    
+    // Latest state root anchored by a batch touching the table
+    pub fn get_table_state_root(&self, database_name: String, table_name: String) -> Option<TableStateRoot> {
+        let token_id = self.table_state_roots.get(&(database_name.clone(), table_name.clone()))?;
+        Some(TableStateRoot {
+            state_root: self.internal_batch_summary(token_id)?.state_root?,
+            token_id: token_id.clone(),
+            database_name,
+            table_name,
+        })
+    }
+    
+    // Verify a row's latest version, or with no `row_hash` its absence,
+    // against the table's latest state root. `proof` holds the 256 siblings
+    // from generate_state_proof, leaf level first; "" is an empty subtree.
+    pub fn verify_row_state(
+        &self,
+        database_name: String,
+        table_name: String,
+        primary_key_hash: String,
+        row_hash: Option<String>,
+        proof: Vec<String>,
+    ) -> bool {
+        let state = match self.get_table_state_root(database_name, table_name.clone()) {
+            Some(state) => state,
+            None => {
+                env::log_str("No state root anchored for this table");
+                return false;
+            }
+        };
+        
+        let key = smt::row_key(&table_name, &primary_key_hash);
+        let leaf = match row_hash.map(|row_hash| merkle::digest_from_hex(&row_hash)) {
+            Some(Some(row_hash)) => smt::leaf_hash(&key, &row_hash),
+            Some(None) => {
+                env::log_str("Row hash must be a 32-byte hex digest");
+                return false;
+            }
+            None => smt::EMPTY.to_vec(),
+        };
+        let siblings = match proof.iter()
+            .map(|sibling| match sibling.as_str() {
+                "" => Some(smt::EMPTY.to_vec()),
+                sibling => merkle::digest_from_hex(sibling),
+            })
+            .collect::<Option<Vec<_>>>()
+        {
+            Some(siblings) => siblings,
+            None => {
+                env::log_str("Invalid state proof: siblings must be 32-byte hex digests");
+                return false;
+            }
+        };
+        
+        let computed_root = smt::root_from_proof(&key, leaf, &siblings);
+        let is_valid = computed_root.as_deref() == merkle::digest_from_hex(&state.state_root).as_deref();
+        
+        env::log_str(&format!(
+            "State verification against batch {} - Expected: {}, Got: {}, Valid: {}",
+            state.token_id, state.state_root,
+            computed_root.as_deref().map(bytes_to_hex).unwrap_or_default(), is_valid
+        ));
+        
+        is_valid
+    }
+    
+    // Get batch summary
+    pub fn get_batch_summary(&self, token_id: TokenId) -> Option<BatchSummary> {
+        self.internal_batch_summary(&token_id)
+    }
+    
+    // Full summary of a batch minted with mint_merkle_batch
+    pub fn get_merkle_batch_summary(&self, token_id: TokenId) -> Option<MerkleTreeBatchSummary> {
+        self.merkle_summaries.get(&token_id).cloned()
+    }
+    
+    // Admin functions
+    
+    // Pausing is an emergency action and never needs approval. Unpausing
+    // goes through a proposal once approvals are enabled.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.assert_role(Role::Pauser);
+        require!(
+            paused || !self.approvals_required(),
+            "Unpausing requires threshold approval, use create_proposal"
+        );
+        self.etrap_settings.paused = paused;
+    }
+    
+    // Grant a role to an account. Admins can be granted by the owner only.
+    pub fn grant_role(&mut self, role: Role, account_id: AccountId) -> bool {
+        self.assert_can_manage_role(role);
+        
+        let granted = self.internal_grant_role(role, account_id.clone());
+        if granted {
+            Self::emit_event("role_granted", json!({
+                "role": role,
+                "account_id": account_id,
+                "sender_id": env::predecessor_account_id(),
+            }));
+        }
+        granted
+    }
+    
+    // Revoke a role from an account. Admins can be revoked by the owner only.
+    pub fn revoke_role(&mut self, role: Role, account_id: AccountId) -> bool {
+        self.assert_can_manage_role(role);
+        
+        let revoked = self.internal_revoke_role(role, &account_id);
+        if revoked {
+            Self::emit_event("role_revoked", json!({
+                "role": role,
+                "account_id": account_id,
+                "sender_id": env::predecessor_account_id(),
+            }));
+        }
+        revoked
+    }
+    
+    // Check whether an account holds a role (the owner holds every role)
+    pub fn has_role(&self, role: Role, account_id: AccountId) -> bool {
+        self.internal_has_role(role, &account_id)
+    }
+    
+    // List accounts explicitly granted a role
+    pub fn get_role_members(&self, role: Role) -> Vec<AccountId> {
+        self.role_members
+            .get(&role)
+            .map(|members| members.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+    
+    // Start a two-step ownership transfer. Passing null cancels a pending proposal.
+    pub fn propose_owner(&mut self, new_owner_id: Option<AccountId>) {
+        self.assert_owner();
+        self.pending_owner_id = new_owner_id.clone();
+        
+        Self::emit_event("owner_proposed", json!({
+            "owner_id": self.owner_id,
+            "pending_owner_id": new_owner_id,
+        }));
+    }
+    
+    // Complete an ownership transfer. Must be called by the proposed owner.
+    pub fn accept_ownership(&mut self) {
+        let caller = env::predecessor_account_id();
+        require!(
+            self.pending_owner_id.as_ref() == Some(&caller),
+            "Only the pending owner can accept ownership"
+        );
+        
+        let previous_owner_id = self.owner_id.replace(caller.clone());
+        self.pending_owner_id = None;
+        
+        Self::emit_event("ownership_transferred", json!({
+            "previous_owner_id": previous_owner_id,
+            "owner_id": caller,
+        }));
+    }
+    
+    // Permanently give up ownership. Roles granted before this remain in effect.
+    pub fn renounce_ownership(&mut self) {
+        self.assert_owner();
+        
+        let previous_owner_id = self.owner_id.take();
+        self.pending_owner_id = None;
+        
+        Self::emit_event("ownership_transferred", json!({
+            "previous_owner_id": previous_owner_id,
+            "owner_id": None::<AccountId>,
+        }));
+    }
+    
+    pub fn get_owner(&self) -> Option<AccountId> {
+        self.owner_id.clone()
+    }
+    
+    pub fn get_pending_owner(&self) -> Option<AccountId> {
+        self.pending_owner_id.clone()
+    }
+    
+    // View method to get contract settings
+    pub fn get_settings(&self) -> serde_json::Value {
+        json!({
+            "fee_recipients": self.etrap_settings.fee_recipients,
+            "fee_amount": self.etrap_settings.fee_amount.as_yoctonear().to_string(),
+            "pricing": self.etrap_settings.pricing,
+            "discount_tiers": self.etrap_settings.discount_tiers,
+            "paused": self.etrap_settings.paused,
+            "owner_id": self.owner_id,
+            "pending_owner_id": self.pending_owner_id,
+            "approval_threshold": self.approval_config.threshold,
+            "approvers": self.approval_config.approvers,
+            "fee_history": self.fee_history.iter().collect::<Vec<_>>()
+        })
+    }
+    
+    // Set the ETRAP fee in yoctoNEAR. Requires a SetFee proposal once
+    // threshold approvals are enabled.
+    pub fn set_fee(&mut self, fee_amount: U128) {
+        self.assert_direct_admin_action_allowed();
+        self.internal_execute_admin_action(AdminAction::SetFee { fee_amount });
+    }
+    
+    // Withdraw escrowed fees to the caller. Any current or former fee recipient
+    // can withdraw its own balance. If the transfer fails the amount is
+    // restored by on_fee_withdrawn.
+    #[payable]
+    pub fn withdraw_fees(&mut self, amount: Option<U128>) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut balance = self.fee_balances.get(&account_id).cloned()
+            .expect("No fees accrued for this account");
+        
+        let available = balance.available();
+        let amount = amount.map(|a| NearToken::from_yoctonear(a.0)).unwrap_or(available);
+        require!(amount > NearToken::from_yoctonear(0), "Nothing to withdraw");
+        require!(
+            amount <= available,
+            format!("Requested {} yoctoNEAR but only {} is available", amount.as_yoctonear(), available.as_yoctonear())
+        );
+        
+        balance.pending = balance.pending.saturating_add(amount);
+        self.fee_balances.insert(account_id.clone(), balance);
+        self.fee_totals.pending = self.fee_totals.pending.saturating_add(amount);
+        
+        Promise::new(account_id.clone())
+            .transfer(amount)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_FEE_WITHDRAWAL_CALLBACK)
+                    .on_fee_withdrawn(account_id, U128(amount.as_yoctonear()))
+            )
+    }
+    
+    // Resolve a fee withdrawal: record it as withdrawn on success, or return
+    // the amount to the available balance if the transfer failed
+    #[private]
+    pub fn on_fee_withdrawn(&mut self, account_id: AccountId, amount: U128) -> bool {
+        let amount = NearToken::from_yoctonear(amount.0);
+        let success = is_promise_success();
+        
+        let mut balance = self.fee_balances.get(&account_id).cloned().unwrap_or_default();
+        balance.pending = balance.pending.saturating_sub(amount);
+        self.fee_totals.pending = self.fee_totals.pending.saturating_sub(amount);
+        if success {
+            balance.withdrawn = balance.withdrawn.saturating_add(amount);
+            self.fee_totals.withdrawn = self.fee_totals.withdrawn.saturating_add(amount);
+        }
+        self.fee_balances.insert(account_id.clone(), balance);
+        
+        Self::emit_event(if success { "fees_withdrawn" } else { "fee_withdrawal_failed" }, json!({
+            "account_id": account_id,
+            "amount": amount.as_yoctonear().to_string(),
+        }));
+        
+        success
+    }
+    
+    // Escrowed fees of a single recipient
+    pub fn get_fee_balance(&self, account_id: AccountId) -> FeeBalanceView {
+        self.fee_balances
+            .get(&account_id)
+            .map(FeeBalanceView::from)
+            .unwrap_or_else(|| FeeBalanceView::from(&FeeBalance::default()))
+    }
+    
+    // Escrowed fees across all recipients
+    pub fn get_fee_totals(&self) -> FeeBalanceView {
+        FeeBalanceView::from(&self.fee_totals)
+    }
+    
+    // Split fees across several recipients by basis-point shares summing to
+    // 10000. Requires a SetFeeRecipients proposal once threshold approvals are enabled.
+    pub fn set_fee_recipients(&mut self, recipients: Vec<FeeRecipient>) {
+        self.assert_direct_admin_action_allowed();
+        self.internal_execute_admin_action(AdminAction::SetFeeRecipients { recipients });
+    }
+    
+    // Set the usage-based fee components. Requires a SetFeePricing proposal
+    // once threshold approvals are enabled.
+    pub fn set_fee_pricing(&mut self, pricing: UsagePricing) {
+        self.assert_direct_admin_action_allowed();
+        self.internal_execute_admin_action(AdminAction::SetFeePricing { pricing });
+    }
+    
+    // Set the monthly volume discount tiers. Requires a SetDiscountTiers
+    // proposal once threshold approvals are enabled.
+    pub fn set_discount_tiers(&mut self, tiers: Vec<DiscountTier>) {
+        self.assert_direct_admin_action_allowed();
+        self.internal_execute_admin_action(AdminAction::SetDiscountTiers { tiers });
+    }
+    
+    // Current volume tier and how many more batches are needed for the next one
+    pub fn get_volume_tier(&self) -> VolumeTierInfo {
+        let batches_this_month = self.batches_minted_this_month();
+        let next_tier = self.etrap_settings.discount_tiers
+            .iter()
+            .find(|tier| tier.min_batches > batches_this_month)
+            .cloned();
+        
+        VolumeTierInfo {
+            month: Self::current_billing_month(),
+            batches_this_month,
+            current_tier: self.current_discount_tier().cloned(),
+            batches_until_next_tier: next_tier.as_ref().map(|tier| tier.min_batches - batches_this_month),
+            next_tier,
+        }
+    }
+    
+    // Quote the ETRAP fee mint_batch would charge for a batch
+    pub fn quote_fee(&self, batch_summary: BatchSummary) -> FeeBreakdown {
+        self.internal_compute_fee(&batch_summary)
+    }
+    
+    // Send 100% of fees to a single treasury account
+    pub fn update_treasury(&mut self, new_treasury: AccountId) {
+        self.assert_direct_admin_action_allowed();
+        self.internal_execute_admin_action(AdminAction::UpdateTreasury { new_treasury });
+    }
+    
+    // Enable threshold approvals. Once enabled, the configuration can only be
+    // changed through a SetApprovalConfig proposal.
+    pub fn set_approval_config(&mut self, config: ApprovalConfig) {
+        self.assert_direct_admin_action_allowed();
+        self.internal_execute_admin_action(AdminAction::SetApprovalConfig { config });
+    }
+    
+    pub fn get_approval_config(&self) -> ApprovalConfig {
+        self.approval_config.clone()
+    }
+    
+    // Propose a sensitive admin action. The proposer's approval is recorded.
+    pub fn create_proposal(&mut self, action: AdminAction) -> u64 {
+        require!(self.approvals_required(), "Threshold approvals are not enabled");
+        self.assert_approver();
+        // Reject bad payloads before they collect approvals only to fail at execution
+        match &action {
+            AdminAction::SetApprovalConfig { config } => Self::validate_approval_config(config),
+            AdminAction::SetFeeRecipients { recipients } => Self::validate_fee_recipients(recipients),
+            _ => {}
+        }
+        
+        let proposer = env::predecessor_account_id();
+        let now = env::block_timestamp();
+        let id = self.next_proposal_id;
+        self.next_proposal_id += 1;
+        
+        let proposal = AdminProposal {
+            id,
+            action,
+            proposer: proposer.clone(),
+            approvals: vec![proposer.clone()],
+            created_at: now,
+            expires_at: now.saturating_add(self.approval_config.proposal_duration_ns),
+        };
+        
+        Self::emit_event("proposal_created", json!({
+            "proposal_id": id,
+            "proposer": proposer,
+            "action": proposal.action,
+            "expires_at": proposal.expires_at,
+        }));
+        
+        self.admin_proposals.insert(id, proposal);
+        id
+    }
+    
+    pub fn approve_proposal(&mut self, proposal_id: u64) {
+        self.assert_approver();
+        let approver = env::predecessor_account_id();
+        
+        let proposal = self.admin_proposals.get_mut(&proposal_id).expect("Proposal not found");
+        require!(env::block_timestamp() < proposal.expires_at, "Proposal has expired");
+        require!(!proposal.approvals.contains(&approver), "Proposal already approved by caller");
+        proposal.approvals.push(approver.clone());
+        
+        Self::emit_event("proposal_approved", json!({
+            "proposal_id": proposal_id,
+            "approver": approver,
+        }));
+    }
+    
+    // Execute a proposal that has reached the approval threshold
+    pub fn execute_proposal(&mut self, proposal_id: u64) {
+        self.assert_approver();
+        
+        let proposal = self.admin_proposals.get(&proposal_id).expect("Proposal not found").clone();
+        require!(env::block_timestamp() < proposal.expires_at, "Proposal has expired");
+        let approvals = self.count_valid_approvals(&proposal);
+        require!(
+            approvals >= self.approval_config.threshold,
+            format!("Not enough approvals: {} of {}", approvals, self.approval_config.threshold)
+        );
+        
+        self.admin_proposals.remove(&proposal_id);
+        self.internal_execute_admin_action(proposal.action.clone());
+        
+        Self::emit_event("proposal_executed", json!({
+            "proposal_id": proposal_id,
+            "action": proposal.action,
+            "executor": env::predecessor_account_id(),
+        }));
+    }
+    
+    // Remove an expired proposal. Callable by anyone.
+    pub fn expire_proposal(&mut self, proposal_id: u64) {
+        let proposal = self.admin_proposals.get(&proposal_id).expect("Proposal not found");
+        require!(env::block_timestamp() >= proposal.expires_at, "Proposal has not expired");
+        self.admin_proposals.remove(&proposal_id);
+        
+        Self::emit_event("proposal_expired", json!({
+            "proposal_id": proposal_id,
+        }));
+    }
+    
+    pub fn get_proposal(&self, proposal_id: u64) -> Option<AdminProposal> {
+        self.admin_proposals.get(&proposal_id).cloned()
+    }
+    
+    // List proposals that have not been executed or expired
+    pub fn get_pending_proposals(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<AdminProposal> {
+        let limit = limit.unwrap_or(50).min(100) as usize;
+        let from_index = from_index.unwrap_or(0) as usize;
+        let now = env::block_timestamp();
+        
+        self.admin_proposals
+            .values()
+            .filter(|proposal| now < proposal.expires_at)
+            .skip(from_index)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+// Implement NFT standard traits
+#[near_bindgen]
+impl NonFungibleTokenCore for ETRAPContract {
+    #[payable]
+    fn nft_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+    ) {
+        self.tokens.nft_transfer(receiver_id, token_id, approval_id, memo)
+    }
+
+    #[payable]
+    fn nft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        self.tokens.nft_transfer_call(receiver_id, token_id, approval_id, memo, msg)
+    }
+
+    fn nft_token(&self, token_id: TokenId) -> Option<Token> {
+        self.tokens.nft_token(token_id)
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenResolver for ETRAPContract {
+    #[private]
+    fn nft_resolve_transfer(
+        &mut self,
+        previous_owner_id: AccountId,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approved_account_ids: Option<HashMap<AccountId, u64>>,
+    ) -> bool {
+        self.tokens.nft_resolve_transfer(
+            previous_owner_id,
+            receiver_id,
+            token_id,
+            approved_account_ids,
+        )
+    }
+}
+
+// NEP-145 storage management. Minters prepay a balance that mint_batch draws
+// on for storage and ETRAP fees, so minting works from function-call access
+// keys that cannot attach deposits.
+#[near_bindgen]
+impl StorageManagement for ETRAPContract {
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount = env::attached_deposit();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let min_balance = Self::storage_balance_min();
+        let registered = self.storage_balances.contains_key(&account_id);
+        
+        if registration_only.unwrap_or(false) {
+            // Only take what registration costs and refund the rest
+            let refund = if registered {
+                amount
+            } else {
+                require!(amount >= min_balance, "The attached deposit is less than the minimum storage balance");
+                self.storage_balances.insert(account_id.clone(), min_balance);
+                amount.saturating_sub(min_balance)
+            };
+            if refund > NearToken::from_yoctonear(0) {
+                Promise::new(env::predecessor_account_id()).transfer(refund);
+            }
+        } else {
+            if !registered {
+                require!(amount >= min_balance, "The attached deposit is less than the minimum storage balance");
+            }
+            let total = self.storage_balances.get(&account_id).copied()
+                .unwrap_or(NearToken::from_yoctonear(0))
+                .saturating_add(amount);
+            self.storage_balances.insert(account_id.clone(), total);
+        }
+        
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+    
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<NearToken>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        require!(self.storage_balances.contains_key(&account_id), "Account is not registered");
+        let available = self.internal_storage_available(&account_id);
+        
+        let amount = amount.unwrap_or(available);
+        require!(amount <= available, "The amount is greater than the available storage balance");
+        
+        if amount > NearToken::from_yoctonear(0) {
+            let balance = self.storage_balances.get_mut(&account_id).unwrap();
+            *balance = balance.saturating_sub(amount);
+            Promise::new(account_id.clone()).transfer(amount);
+        }
+        
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+    
+    // Removes the prepaid balance and refunds it in full. Minted tokens are
+    // not affected, so `force` has no additional meaning here.
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        let _ = force;
+        let account_id = env::predecessor_account_id();
+        
+        match self.storage_balances.remove(&account_id) {
+            Some(total) => {
+                Promise::new(account_id).transfer(total);
+                true
+            }
+            None => false,
+        }
+    }
+    
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: Self::storage_balance_min(),
+            max: None,
+        }
+    }
+    
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.internal_storage_balance_of(&account_id)
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenMetadataProvider for ETRAPContract {
+    fn nft_metadata(&self) -> NFTContractMetadata {
+        self.metadata.clone()
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenEnumeration for ETRAPContract {
+    fn nft_total_supply(&self) -> U128 {
+        self.tokens.nft_total_supply()
+    }
+
+    fn nft_tokens(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+        self.tokens.nft_tokens(from_index, limit)
+    }
+
+    fn nft_supply_for_owner(&self, account_id: AccountId) -> U128 {
+        self.tokens.nft_supply_for_owner(account_id)
+    }
+
+    fn nft_tokens_for_owner(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Token> {
+        self.tokens.nft_tokens_for_owner(account_id, from_index, limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+    
+    fn reset_context() {
+        let context = VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(0))
+            .build();
+        testing_env!(context);
+    }
+    
+    fn set_caller(account_id: AccountId, attached_deposit: NearToken) {
+        let context = VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(account_id)
+            .attached_deposit(attached_deposit)
+            .build();
+        testing_env!(context);
+    }
+    
+    fn setup_contract() -> ETRAPContract {
+        reset_context();
+        ETRAPContract::new_with_yocto_fee(
+            accounts(0),
+            "Test Organization".to_string(),
+            accounts(1),
+            U128(0),
+        )
+    }
+    
+    fn batch_summary(merkle_root: String, tree_version: TreeVersion, tx_count: u32) -> BatchSummary {
+        BatchSummary {
+            database_name: "testdb".to_string(),
+            table_names: vec!["orders".to_string()],
+            tx_count,
+            hash_algorithm: Some(HashAlgorithm::infer(&merkle_root)),
+            merkle_root,
+            s3_bucket: "bucket".to_string(),
+            s3_key: "key".to_string(),
+            operation_counts: OperationCounts { inserts: tx_count, ..Default::default() },
+            tree_version,
+            leaf_count: Some(tx_count),
+            ..Default::default()
+        }
+    }
+    
+    // Builds a batch of `leaf_count` transactions through the view helpers
+    // and checks the first, middle and last leaves against the stored root.
+    // Each call gets a fresh context so the gas limit applies per call.
+    fn assert_conformance(contract: &mut ETRAPContract, tree_version: TreeVersion, leaf_count: u32) {
+        let transactions: Vec<String> = (0..leaf_count).map(|i| format!("tx-{}", i)).collect();
+        reset_context();
+        let root = contract.compute_merkle_root(transactions.clone(), true, Some(tree_version), None, None);
+        let token_id = format!("batch-{:?}-{}", tree_version, leaf_count);
+        contract.batch_summaries.insert(
+            token_id.clone(),
+            batch_summary(root, tree_version, leaf_count).into(),
+        );
+        
+        for index in [0, leaf_count / 2, leaf_count - 1] {
+            reset_context();
+            let proof = contract.generate_merkle_proof(
+                transactions.clone(), index, true, Some(tree_version), None, None,
+            );
+            reset_context();
+            assert!(
+                contract.verify_document_in_batch(
+                    token_id.clone(),
+                    transactions[index as usize].clone(),
+                    proof,
+                    index,
+                    Some(leaf_count),
+                    None,
+                ),
+                "{:?} tree: leaf {} of {} did not verify", tree_version, index, leaf_count
+            );
+        }
+    }
+    
+    // Every size up to 1025 is covered for every leaf in merkle::tests; hashing
+    // through the mocked host is slow, so here all small trees are checked plus
+    // the sizes around each power of two up to 1025
+    #[test]
+    fn verify_accepts_trees_up_to_1025_leaves() {
+        let mut contract = setup_contract();
+        let sizes: Vec<u32> = (1..=65)
+            .chain((7..=10).flat_map(|exp| [(1 << exp) - 1, 1 << exp, (1 << exp) + 1]))
+            .collect();
+        
+        for tree_version in [TreeVersion::Legacy, TreeVersion::Rfc6962, TreeVersion::Binary] {
+            for &leaf_count in &sizes {
+                assert_conformance(&mut contract, tree_version, leaf_count);
+            }
+        }
+    }
+    
+    #[test]
+    fn multiproofs_verify_through_the_contract() {
+        let mut contract = setup_contract();
+        let transactions: Vec<String> = (0..13).map(|i| format!("tx-{}", i)).collect();
+        
+        for tree_version in [TreeVersion::Legacy, TreeVersion::Rfc6962, TreeVersion::Binary] {
+            let root = contract.compute_merkle_root(transactions.clone(), true, Some(tree_version), None, None);
+            let token_id = format!("batch-{:?}", tree_version);
+            contract.batch_summaries.insert(token_id.clone(), batch_summary(root, tree_version, 13).into());
+            
+            let indices = vec![12, 0, 5, 4];
+            let multiproof = contract.generate_merkle_multiproof(
+                transactions.clone(), indices.clone(), true, Some(tree_version), None, None,
+            );
+            let leaves: Vec<String> = indices.iter().map(|&i| transactions[i as usize].clone()).collect();
+            
+            assert!(contract.verify_documents_in_batch(
+                token_id.clone(), leaves.clone(), indices.clone(), multiproof.clone(), None, None,
+            ));
+            
+            let mut swapped = leaves.clone();
+            swapped.swap(0, 1);
+            assert!(!contract.verify_documents_in_batch(
+                token_id.clone(), swapped, indices.clone(), multiproof.clone(), None, None,
+            ));
+            assert!(!contract.verify_documents_in_batch(
+                token_id.clone(), leaves.clone(), indices.clone(), multiproof[1..].to_vec(), None, None,
+            ));
+            assert!(!contract.verify_documents_in_batch(
+                token_id, leaves, indices, multiproof, Some(14), None,
+            ));
+        }
+    }
+    
+    #[test]
+    fn single_proofs_and_multiproofs_share_a_root() {
+        let mut contract = setup_contract();
+        let transactions: Vec<String> = (0..7).map(|i| format!("tx-{}", i)).collect();
+        let indices = vec![1u32, 4, 6];
+        let leaves: Vec<String> = indices.iter().map(|&i| transactions[i as usize].clone()).collect();
+        let schemes = [
+            (TreeVersion::Legacy, false),
+            (TreeVersion::Legacy, true),
+            (TreeVersion::Rfc6962, true),
+            (TreeVersion::Binary, true),
+        ];
+        
+        for (tree_version, use_sha256) in schemes {
+            for leaf_encoding in [None, Some(LeafEncoding::Utf8)] {
+                reset_context();
+                let root = contract.compute_merkle_root(
+                    transactions.clone(), use_sha256, Some(tree_version), None, leaf_encoding,
+                );
+                let token_id = format!("{:?}-{}-{:?}", tree_version, use_sha256, leaf_encoding);
+                contract.batch_summaries.insert(token_id.clone(), batch_summary(root, tree_version, 7).into());
+                
+                for &index in &indices {
+                    reset_context();
+                    let proof = contract.generate_merkle_proof(
+                        transactions.clone(), index, use_sha256, Some(tree_version), None, leaf_encoding,
+                    );
+                    let result = contract.verify_document_detailed(
+                        token_id.clone(), transactions[index as usize].clone(), proof, index, None, leaf_encoding,
+                    );
+                    assert!(result.valid, "{}: leaf {}", token_id, index);
+                }
+                
+                reset_context();
+                let multiproof = contract.generate_merkle_multiproof(
+                    transactions.clone(), indices.clone(), use_sha256, Some(tree_version), None, leaf_encoding,
+                );
+                assert!(contract.verify_documents_in_batch(
+                    token_id.clone(), leaves.clone(), indices.clone(), multiproof, None, leaf_encoding,
+                ), "{}", token_id);
+            }
+        }
+    }
+    
+    #[test]
+    fn non_membership_is_proven_by_adjacent_neighbors() {
+        let mut contract = setup_contract();
+        // Unsorted input; the batch commits to the sorted order
+        let transactions: Vec<String> = [7u8, 3, 11, 1, 9]
+            .iter()
+            .map(|&i| merkle::bytes_to_hex(&[i; 32]))
+            .collect();
+        let mut sorted = transactions.clone();
+        sorted.sort();
+        let key = |i: u8| merkle::bytes_to_hex(&[i; 32]);
+        let digest = Some(LeafEncoding::Digest);
+        
+        for tree_version in [TreeVersion::Legacy, TreeVersion::Rfc6962, TreeVersion::Binary] {
+            reset_context();
+            let root = contract.compute_merkle_root(sorted.clone(), true, Some(tree_version), None, digest);
+            let token_id = format!("sorted-{:?}", tree_version);
+            let mut summary = batch_summary(root, tree_version, 5);
+            summary.sorted_leaves = true;
+            contract.batch_summaries.insert(token_id.clone(), summary.into());
+            
+            // Between two leaves, below the first and above the last
+            for absent in [4u8, 0, 12] {
+                let proof = contract
+                    .generate_non_membership_proof(transactions.clone(), key(absent), Some(tree_version), None, digest)
+                    .unwrap();
+                assert!(contract.verify_non_membership(
+                    token_id.clone(), key(absent), proof.left_neighbor_proof, proof.right_neighbor_proof, digest,
+                ), "{:?} {}", tree_version, absent);
+            }
+            assert!(contract
+                .generate_non_membership_proof(transactions.clone(), key(9), Some(tree_version), None, digest)
+                .is_none());
+            
+            // Valid inclusion proofs that skip a leaf do not prove absence
+            let proof = contract
+                .generate_non_membership_proof(transactions.clone(), key(4), Some(tree_version), None, digest)
+                .unwrap();
+            let skipping = contract
+                .generate_non_membership_proof(transactions.clone(), key(8), Some(tree_version), None, digest)
+                .unwrap();
+            assert!(!contract.verify_non_membership(
+                token_id.clone(), key(7), proof.left_neighbor_proof.clone(), skipping.right_neighbor_proof, digest,
+            ));
+            // Neighbors that don't bracket the key
+            assert!(!contract.verify_non_membership(
+                token_id.clone(), key(8), proof.left_neighbor_proof.clone(), proof.right_neighbor_proof.clone(), digest,
+            ));
+            assert!(!contract.verify_non_membership(token_id.clone(), key(4), None, None, digest));
+            
+            // Unsorted batches cannot prove absence
+            let mut unsorted = contract.get_batch_summary(token_id.clone()).unwrap();
+            unsorted.sorted_leaves = false;
+            contract.batch_summaries.insert(token_id.clone(), unsorted.into());
+            assert!(!contract.verify_non_membership(
+                token_id, key(4), proof.left_neighbor_proof, proof.right_neighbor_proof, digest,
+            ));
+        }
+    }
+    
+    #[test]
+    fn raw_keys_whose_hash_is_a_leaf_are_not_absent() {
+        let mut contract = setup_contract();
+        let mut transactions: Vec<String> = ["hello", "world", "etrap"]
+            .iter()
+            .map(|document| merkle::bytes_to_hex(&env::sha256(document.as_bytes())))
+            .collect();
+        transactions.sort();
+        // Only the legacy encoding mixes digests and raw documents
+        let legacy = Some(LeafEncoding::Legacy);
+        let root = contract.compute_merkle_root(transactions.clone(), true, None, None, legacy);
+        let mut summary = batch_summary(root, TreeVersion::Legacy, 3);
+        summary.sorted_leaves = true;
+        contract.batch_summaries.insert("sorted".to_string(), summary.into());
+        
+        assert!(contract
+            .generate_non_membership_proof(transactions.clone(), "hello".to_string(), None, None, legacy)
+            .is_none());
+        
+        // As text "hello" sorts after every hex leaf, but its hash is a leaf
+        let last = transactions.len() as u32 - 1;
+        let last_leaf = NeighborProof {
+            document_hash: transactions[last as usize].clone(),
+            leaf_index: last,
+            merkle_proof: contract.generate_merkle_proof(
+                transactions.clone(), last, true, None, None, Some(LeafEncoding::Digest),
+            ),
+        };
+        assert!(contract.verify_document_in_batch(
+            "sorted".to_string(), last_leaf.document_hash.clone(), last_leaf.merkle_proof.clone(), last, None, legacy,
+        ));
+        assert!(!contract.verify_non_membership(
+            "sorted".to_string(), "hello".to_string(), Some(last_leaf), None, legacy,
+        ));
+        
+        // A raw key whose hash is not a leaf is still provably absent
+        let proof = contract
+            .generate_non_membership_proof(transactions.clone(), "absent".to_string(), None, None, legacy)
+            .unwrap();
+        assert!(contract.verify_non_membership(
+            "sorted".to_string(), "absent".to_string(), proof.left_neighbor_proof, proof.right_neighbor_proof, legacy,
+        ));
+    }
+    
+    #[test]
+    fn row_state_verifies_against_the_latest_state_root() {
+        let mut contract = setup_contract();
+        let row = |table: &str, pk: &str, version: u8| RowState {
+            table_name: table.to_string(),
+            primary_key_hash: pk.to_string(),
+            row_hash: merkle::bytes_to_hex(&[version; 32]),
+        };
+        let mint = |contract: &mut ETRAPContract, token_id: &str, tables: &[&str], rows: &[RowState]| {
+            reset_context();
+            let mut summary = simple_summary(token_id, "testdb");
+            summary.table_names = tables.iter().map(|t| t.to_string()).collect();
+            summary.state_root = contract.compute_state_root(rows.to_vec());
+            mint_linked(contract, accounts(0), token_id, summary);
+        };
+        let verify = |contract: &ETRAPContract, table: &str, pk: &str, row_hash: Option<String>, rows: &[RowState]| {
+            reset_context();
+            let proof = contract.generate_state_proof(rows.to_vec(), table.to_string(), pk.to_string()).unwrap();
+            reset_context();
+            contract.verify_row_state("testdb".to_string(), table.to_string(), pk.to_string(), row_hash, proof)
+        };
+        
+        let first = vec![row("users", "1", 1), row("users", "2", 1), row("orders", "1", 1)];
+        mint(&mut contract, "state-1", &["users", "orders"], &first);
+        assert!(verify(&contract, "users", "1", Some(first[0].row_hash.clone()), &first));
+        assert!(verify(&contract, "users", "3", None, &first));
+        assert!(!verify(&contract, "users", "2", None, &first));
+        
+        // Only users changed; orders keeps the state root of state-1
+        let second = vec![row("users", "1", 2), row("users", "3", 1), row("orders", "1", 1)];
+        mint(&mut contract, "state-2", &["users"], &second);
+        assert_eq!(
+            contract.get_table_state_root("testdb".to_string(), "orders".to_string()).unwrap().token_id,
+            "state-1"
+        );
+        assert!(verify(&contract, "users", "1", Some(second[0].row_hash.clone()), &second));
+        assert!(!verify(&contract, "users", "1", Some(first[0].row_hash.clone()), &second));
+        assert!(!verify(&contract, "users", "1", Some(first[0].row_hash.clone()), &first));
+        assert!(verify(&contract, "orders", "1", Some(first[2].row_hash.clone()), &first));
+        
+        reset_context();
+        assert!(contract.compute_state_root(vec![row("users", "1", 1), row("users", "1", 2)]).is_none());
+        assert!(contract.get_table_state_root("testdb".to_string(), "items".to_string()).is_none());
+    }
+    
+    #[test]
+    fn detailed_verification_reports_why_a_proof_fails() {
+        let mut contract = setup_contract();
+        let transactions: Vec<String> = (0..5).map(|i| format!("tx-{}", i)).collect();
+        let root = contract.compute_merkle_root(transactions.clone(), true, None, None, None);
+        contract.batch_summaries.insert("batch".to_string(), batch_summary(root.clone(), TreeVersion::Legacy, 5).into());
+        let rfc_root = contract.compute_merkle_root(transactions.clone(), true, Some(TreeVersion::Rfc6962), None, None);
+        contract.batch_summaries.insert("rfc".to_string(), batch_summary(rfc_root, TreeVersion::Rfc6962, 5).into());
+        let proof = contract.generate_merkle_proof(transactions.clone(), 2, true, None, None, None);
+        let detailed = |token_id: &str, document: &str, proof: Vec<String>, leaf_index: u32, leaf_count: Option<u32>| {
+            reset_context();
+            contract.verify_document_detailed(
+                token_id.to_string(), document.to_string(), proof, leaf_index, leaf_count, None,
+            )
+        };
+        
+        let result = detailed("batch", &transactions[2], proof.clone(), 2, None);
+        assert!(result.valid);
+        assert_eq!(result.failure, None);
+        assert_eq!(result.computed_root, Some(root.clone()));
+        assert_eq!(result.hash_algorithm, Some(HashAlgorithm::Sha256));
+        assert_eq!(result.timestamp, None);
+        
+        let result = detailed("batch", "tx-9", proof.clone(), 2, None);
+        assert_eq!(result.failure, Some(VerificationFailure::RootMismatch));
+        assert!(result.computed_root.is_some_and(|computed| computed != root));
         
-        // Build complete tree
-        let mut current_level: Vec<String> = if use_sha256 {
-            transactions.iter()
-                .map(|tx| {
-                    let hash_bytes = env::sha256(tx.as_bytes());
-                    Self::bytes_to_hex(&hash_bytes)
+        let failure = |token_id, proof, leaf_index, leaf_count| {
+            detailed(token_id, &transactions[2], proof, leaf_index, leaf_count).failure
+        };
+        assert_eq!(failure("batch", proof.clone(), 5, None), Some(VerificationFailure::IndexOutOfRange));
+        assert_eq!(failure("batch", proof[1..].to_vec(), 2, None), Some(VerificationFailure::BadProofLength));
+        assert_eq!(failure("batch", proof.clone(), 2, Some(6)), Some(VerificationFailure::LeafCountMismatch));
+        assert_eq!(failure("missing", proof, 2, None), Some(VerificationFailure::BatchNotFound));
+        assert_eq!(failure("rfc", vec!["zz".to_string(); 3], 0, None), Some(VerificationFailure::InvalidProofElement));
+    }
+    
+    #[test]
+    fn legacy_roots_hash_hex_strings_or_concatenate() {
+        let contract = setup_contract();
+        let legacy = Some(LeafEncoding::Legacy);
+        let hex_sha256 = |data: &str| merkle::bytes_to_hex(&env::sha256(data.as_bytes()));
+        let digest = hex_sha256("already hashed");
+        let transactions = vec!["tx-0".to_string(), format!("0x{}", digest), "tx-2".to_string()];
+        
+        // Text is hashed, hex digests are kept, and nodes hash the
+        // concatenated hex strings of their children
+        let expected = hex_sha256(&format!(
+            "{}{}",
+            hex_sha256(&format!("{}{}", hex_sha256("tx-0"), digest)),
+            hex_sha256("tx-2"),
+        ));
+        assert_eq!(contract.compute_merkle_root(transactions.clone(), true, None, None, legacy), expected);
+        
+        let parts = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        assert_eq!(contract.compute_merkle_root(parts.clone(), false, None, None, legacy), "abc");
+        assert_eq!(
+            contract.compute_merkle_root(parts, true, None, Some(HashAlgorithm::SimpleConcat), legacy),
+            "simple_concat:abc"
+        );
+    }
+    
+    #[test]
+    fn explicit_leaf_encodings_agree_across_methods() {
+        use near_sdk::base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+        
+        let mut contract = setup_contract();
+        let documents: Vec<String> = (0..6).map(|i| format!("tx-{}", i)).collect();
+        let encode = |encoding: LeafEncoding| -> Vec<String> {
+            documents.iter()
+                .map(|document| match encoding {
+                    LeafEncoding::Digest => merkle::bytes_to_hex(&env::sha256(document.as_bytes())),
+                    LeafEncoding::Base64 => BASE64.encode(document),
+                    _ => document.clone(),
                 })
                 .collect()
-        } else {
-            transactions
         };
         
-        tree.push(current_level.clone());
-        
-        while current_level.len() > 1 {
-            let mut next_level = Vec::new();
+        for tree_version in [TreeVersion::Legacy, TreeVersion::Rfc6962, TreeVersion::Binary] {
+            let mut roots = vec![];
+            for encoding in [LeafEncoding::Digest, LeafEncoding::Utf8, LeafEncoding::Base64] {
+                reset_context();
+                let leaves = encode(encoding);
+                let root = contract.compute_merkle_root(
+                    leaves.clone(), true, Some(tree_version), None, Some(encoding),
+                );
+                let token_id = format!("{:?}-{:?}", tree_version, encoding);
+                contract.batch_summaries.insert(token_id.clone(), batch_summary(root.clone(), tree_version, 6).into());
+                
+                for index in 0..6 {
+                    reset_context();
+                    let proof = contract.generate_merkle_proof(
+                        leaves.clone(), index, true, Some(tree_version), None, Some(encoding),
+                    );
+                    assert!(contract.verify_document_in_batch(
+                        token_id.clone(), leaves[index as usize].clone(), proof, index, None, Some(encoding),
+                    ), "{}: leaf {}", token_id, index);
+                }
+                roots.push(root);
+            }
             
-            for i in (0..current_level.len()).step_by(2) {
-                if i + 1 < current_level.len() {
-                    let combined = if use_sha256 {
-                        let concat = format!("{}{}", current_level[i], current_level[i + 1]);
-                        let hash_bytes = env::sha256(concat.as_bytes());
-                        Self::bytes_to_hex(&hash_bytes)
-                    } else {
-                        format!("{}{}", current_level[i], current_level[i + 1])
-                    };
-                    next_level.push(combined);
-                } else {
-                    next_level.push(current_level[i].clone());
+            // The same documents give the same root as text or base64 bytes, and
+            // as digests except where RFC 6962 hashes the raw document itself
+            assert_eq!(roots[1], roots[2]);
+            assert_eq!(roots[0] == roots[1], tree_version != TreeVersion::Rfc6962);
+        }
+        
+        // The legacy encoding keeps 64-character digests as leaves when
+        // computing the root but hashes them again when generating a proof
+        reset_context();
+        let digests = encode(LeafEncoding::Digest);
+        let legacy = Some(LeafEncoding::Legacy);
+        let root = contract.compute_merkle_root(digests.clone(), true, None, None, legacy);
+        contract.batch_summaries.insert("legacy".to_string(), batch_summary(root, TreeVersion::Legacy, 6).into());
+        let proof = contract.generate_merkle_proof(digests.clone(), 1, true, None, None, legacy);
+        assert!(!contract.verify_document_in_batch(
+            "legacy".to_string(), digests[1].clone(), proof, 1, None, legacy,
+        ));
+        
+        // Without an encoding the same digests are read as text by every method
+        reset_context();
+        let root = contract.compute_merkle_root(digests.clone(), true, None, None, None);
+        assert_eq!(root, contract.compute_merkle_root(digests.clone(), true, None, None, Some(LeafEncoding::Utf8)));
+        contract.batch_summaries.insert("default".to_string(), batch_summary(root, TreeVersion::Legacy, 6).into());
+        let proof = contract.generate_merkle_proof(digests.clone(), 1, true, None, None, None);
+        assert!(contract.verify_document_in_batch(
+            "default".to_string(), digests[1].clone(), proof, 1, None, None,
+        ));
+        let multiproof = contract.generate_merkle_multiproof(digests.clone(), vec![1, 4], true, None, None, None);
+        assert!(contract.verify_documents_in_batch(
+            "default".to_string(), vec![digests[1].clone(), digests[4].clone()], vec![1, 4], multiproof, None, None,
+        ));
+        
+        reset_context();
+        assert_eq!(contract.compute_merkle_root(documents.clone(), true, None, None, Some(LeafEncoding::Digest)), "");
+        let proof = contract.generate_merkle_proof(digests, 0, true, None, None, Some(LeafEncoding::Digest));
+        let result = contract.verify_document_detailed(
+            "Legacy-Digest".to_string(), "tx-0".to_string(), proof, 0, None, Some(LeafEncoding::Digest),
+        );
+        assert_eq!(result.failure, Some(VerificationFailure::InvalidDocument));
+    }
+    
+    #[test]
+    fn leaf_encodings_reach_multiproofs_and_non_membership() {
+        use near_sdk::base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+        
+        let mut contract = setup_contract();
+        for tree_version in [TreeVersion::Legacy, TreeVersion::Rfc6962, TreeVersion::Binary] {
+            for encoding in [LeafEncoding::Utf8, LeafEncoding::Base64] {
+                reset_context();
+                let encode = |document: String| match encoding {
+                    LeafEncoding::Base64 => BASE64.encode(document),
+                    _ => document,
+                };
+                let documents: Vec<String> = (0..5).map(|i| encode(format!("doc-{}", i))).collect();
+                let mut sorted = documents.clone();
+                sorted.sort_by_cached_key(|document| {
+                    merkle::sort_digest(HashAlgorithm::Sha256, encoding, document).unwrap()
+                });
+                let root = contract.compute_merkle_root(sorted.clone(), true, Some(tree_version), None, Some(encoding));
+                let token_id = format!("{:?}-{:?}", tree_version, encoding);
+                let mut summary = batch_summary(root, tree_version, 5);
+                summary.sorted_leaves = true;
+                contract.batch_summaries.insert(token_id.clone(), summary.into());
+                
+                reset_context();
+                let indices = vec![0, 3];
+                let multiproof = contract.generate_merkle_multiproof(
+                    documents.clone(), indices.clone(), true, Some(tree_version), None, Some(encoding),
+                );
+                let proven: Vec<String> = indices.iter().map(|&i| documents[i as usize].clone()).collect();
+                let unsorted_root = contract.compute_merkle_root(documents.clone(), true, Some(tree_version), None, Some(encoding));
+                let unsorted_id = format!("{}-unsorted", token_id);
+                contract.batch_summaries.insert(unsorted_id.clone(), batch_summary(unsorted_root, tree_version, 5).into());
+                assert!(contract.verify_documents_in_batch(
+                    unsorted_id.clone(), proven.clone(), indices.clone(), multiproof.clone(), None, Some(encoding),
+                ), "{}", token_id);
+                if encoding == LeafEncoding::Base64 {
+                    // Read as legacy text, the encoded documents hash to other leaves
+                    assert!(!contract.verify_documents_in_batch(
+                        unsorted_id, proven, indices, multiproof, None, None,
+                    ), "{}", token_id);
                 }
+                
+                reset_context();
+                let absent = encode("missing".to_string());
+                let proof = contract
+                    .generate_non_membership_proof(documents.clone(), absent.clone(), Some(tree_version), None, Some(encoding))
+                    .unwrap();
+                assert!(contract.verify_non_membership(
+                    token_id.clone(), absent, proof.left_neighbor_proof, proof.right_neighbor_proof, Some(encoding),
+                ), "{}", token_id);
+                assert!(contract
+                    .generate_non_membership_proof(documents.clone(), documents[2].clone(), Some(tree_version), None, Some(encoding))
+                    .is_none());
             }
+        }
+    }
+    
+    #[test]
+    fn verify_rejects_proofs_that_do_not_fit_the_leaf_count() {
+        let mut contract = setup_contract();
+        let transactions: Vec<String> = (0..5).map(|i| format!("tx-{}", i)).collect();
+        let root = contract.compute_merkle_root(transactions.clone(), true, None, None, None);
+        contract.batch_summaries.insert(
+            "batch".to_string(),
+            batch_summary(root, TreeVersion::Legacy, 5).into(),
+        );
+        let proof = contract.generate_merkle_proof(transactions.clone(), 4, true, None, None, None);
+        let verify = |proof: Vec<String>, leaf_index: u32, leaf_count: Option<u32>| {
+            contract.verify_document_in_batch(
+                "batch".to_string(), transactions[4].clone(), proof, leaf_index, leaf_count, None,
+            )
+        };
+        
+        assert!(verify(proof.clone(), 4, None));
+        assert!(verify(proof.clone(), 4, Some(5)));
+        assert!(!verify(proof.clone(), 4, Some(8)));
+        assert!(!verify(proof.clone(), 5, None));
+        
+        let mut too_long = proof.clone();
+        too_long.push(transactions[0].clone());
+        assert!(!verify(too_long, 4, None));
+        assert!(!verify(vec![], 4, None));
+    }
+    
+    #[test]
+    fn batches_without_a_recorded_leaf_count_use_the_given_one() {
+        let mut contract = setup_contract();
+        let transactions: Vec<String> = (0..5).map(|i| format!("tx-{}", i)).collect();
+        let root = contract.compute_merkle_root(transactions.clone(), true, None, None, None);
+        let mut summary = batch_summary(root, TreeVersion::Legacy, 5);
+        summary.leaf_count = None;
+        contract.batch_summaries.insert("batch".to_string(), summary.into());
+        let proof = contract.generate_merkle_proof(transactions.clone(), 4, true, None, None, None);
+        
+        // Without any leaf count the promoted last leaf is walked from the wrong side
+        assert!(!contract.verify_document_in_batch(
+            "batch".to_string(), transactions[4].clone(), proof.clone(), 4, None, None,
+        ));
+        assert!(contract.verify_document_in_batch(
+            "batch".to_string(), transactions[4].clone(), proof, 4, Some(5), None,
+        ));
+    }
+    
+    #[test]
+    fn summaries_stored_before_versioning_stay_readable() {
+        let mut contract = setup_contract();
+        let transactions: Vec<String> = (0..5).map(|i| format!("tx-{}", i)).collect();
+        let root = contract.compute_merkle_root(transactions.clone(), true, None, None, None);
+        contract.legacy_batch_summaries.insert("legacy".to_string(), BatchSummaryV0 {
+            database_name: "testdb".to_string(),
+            table_names: vec!["orders".to_string()],
+            timestamp: 1_700_000_000_000,
+            tx_count: 5,
+            merkle_root: root.clone(),
+            s3_bucket: "bucket".to_string(),
+            s3_key: "key".to_string(),
+            size_bytes: 100,
+            operation_counts: OperationCounts { inserts: 5, ..Default::default() },
+        });
+        contract.legacy_batch_summaries.flush();
+        
+        let summary = contract.get_batch_summary("legacy".to_string()).unwrap();
+        assert_eq!(summary.merkle_root, root);
+        assert_eq!(summary.tree_version, TreeVersion::Legacy);
+        assert_eq!(summary.leaf_count, None);
+        assert_eq!(summary.previous_batch_id, None);
+        
+        let proof = contract.generate_merkle_proof(transactions.clone(), 4, true, None, None, None);
+        assert!(contract.verify_document_in_batch(
+            "legacy".to_string(), transactions[4].clone(), proof, 4, Some(5), None,
+        ));
+    }
+    
+    #[test]
+    fn migrate_keeps_the_state_of_the_original_contract() {
+        reset_context();
+        let mut old = ETRAPContractV0 {
+            tokens: NonFungibleToken::new(
+                StorageKey::NonFungibleToken,
+                accounts(0),
+                Some(StorageKey::TokenMetadata),
+                Some(StorageKey::Enumeration),
+                Some(StorageKey::Approval),
+            ),
+            metadata: NFTContractMetadata {
+                spec: NFT_METADATA_SPEC.to_string(),
+                name: "ETRAP Document Integrity Certificates - Test".to_string(),
+                symbol: "ETRAP-TEST".to_string(),
+                icon: None,
+                base_uri: None,
+                reference: None,
+                reference_hash: None,
+            },
+            batch_summaries: LookupMap::new(StorageKey::BatchSummaries),
+            tokens_by_database: LookupMap::new(StorageKey::TokensByDatabase),
+            tokens_by_month: LookupMap::new(StorageKey::TokensByMonth),
+            tokens_by_timestamp: IterableMap::new(StorageKey::TokensByTimestamp),
+            recent_tokens: Vector::new(StorageKey::RecentTokens),
+            tokens_by_table: LookupMap::new(StorageKey::TokensByTable),
+            total_batches_per_database: LookupMap::new(StorageKey::TotalBatchesPerDatabase),
+            database_list: IterableSet::new(StorageKey::DatabaseList),
+            etrap_settings: ETRAPSettingsV0 {
+                fee_amount: NearToken::from_millinear(10),
+                etrap_treasury: accounts(1),
+                paused: true,
+            },
+        };
+        
+        let transactions: Vec<String> = (0..3).map(|i| format!("tx-{}", i)).collect();
+        let levels = ETRAPContract::tree_levels(TreeVersion::Legacy, HashAlgorithm::Sha256, &transactions);
+        let root = ETRAPContract::node_to_string(TreeVersion::Legacy, &levels.last().unwrap()[0]);
+        old.tokens.internal_mint_with_refund("old-batch".to_string(), accounts(0), Some(TokenMetadata::default()), None);
+        old.batch_summaries.insert("old-batch".to_string(), BatchSummaryV0 {
+            database_name: "testdb".to_string(),
+            table_names: vec![],
+            timestamp: 1_700_000_000_000,
+            tx_count: 3,
+            merkle_root: root.clone(),
+            s3_bucket: "bucket".to_string(),
+            s3_key: "key".to_string(),
+            size_bytes: 100,
+            operation_counts: OperationCounts::default(),
+        });
+        let mut db_tokens = IterableSet::new(StorageKey::TokensByDatabaseInner {
+            database_hash: env::sha256(b"testdb"),
+        });
+        db_tokens.insert("old-batch".to_string());
+        db_tokens.flush();
+        old.tokens_by_database.insert("testdb".to_string(), db_tokens);
+        old.total_batches_per_database.insert("testdb".to_string(), 1);
+        old.database_list.insert("testdb".to_string());
+        env::state_write(&old);
+        drop(old);
+        
+        let mut contract = ETRAPContract::migrate();
+        assert_eq!(contract.get_owner(), Some(accounts(0)));
+        let settings = contract.get_settings();
+        assert_eq!(settings["fee_amount"], NearToken::from_millinear(10).as_yoctonear().to_string());
+        assert_eq!(settings["paused"], true);
+        assert_eq!(settings["fee_recipients"][0]["account_id"], accounts(1).to_string());
+        assert_eq!(settings["fee_history"].as_array().unwrap().len(), 1);
+        assert!(contract.nft_token("old-batch".to_string()).is_some());
+        assert_eq!(contract.get_batch_summary("old-batch".to_string()).unwrap().merkle_root, root);
+        
+        let proof = contract.generate_merkle_proof(transactions.clone(), 1, true, None, None, None);
+        assert!(contract.verify_document_in_batch(
+            "old-batch".to_string(), transactions[1].clone(), proof, 1, Some(3), None,
+        ));
+        
+        contract.set_paused(false);
+        mint_linked(&mut contract, accounts(0), "new-batch", simple_summary("new-batch", "testdb"));
+        reset_context();
+        assert_eq!(contract.get_batches_by_database("testdb".to_string(), None, None).total_count, 2);
+        assert!(contract.get_batch_summary("new-batch".to_string()).is_some());
+    }
+    
+    fn merkle_summary(batch_id: &str, merkle_root: String, leaf_count: u32) -> MerkleTreeBatchSummary {
+        MerkleTreeBatchSummary {
+            merkle_root,
+            leaf_count,
+            hash_algorithm: "SHA256".to_string(),
+            batch_id: batch_id.to_string(),
+            organization_id: "org".to_string(),
+            database_name: "testdb".to_string(),
+            batch_summary: BatchSummaryInfo {
+                start_timestamp: 1_700_000_000_000,
+                end_timestamp: 1_700_000_060_000,
+                total_transactions: leaf_count,
+                operations_summary: OperationsSummary { inserts: leaf_count, ..Default::default() },
+            },
+            s3_bucket: "bucket".to_string(),
+            s3_prefix: format!("testdb/{}/", batch_id),
+            ..Default::default()
+        }
+    }
+    
+    #[test]
+    fn mint_merkle_batch_stores_the_typed_summary() {
+        let mut contract = setup_contract();
+        let transactions: Vec<String> = (0..3).map(|i| format!("tx-{}", i)).collect();
+        let root = contract.compute_merkle_root(
+            transactions.clone(), true, Some(TreeVersion::Rfc6962), None, None,
+        );
+        let state_root = merkle::bytes_to_hex(&[7; 32]);
+        
+        let context = VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_near(1))
+            .block_height(42)
+            .build();
+        testing_env!(context);
+        
+        contract.mint_merkle_batch(
+            "BATCH-001".to_string(),
+            accounts(0),
+            TokenMetadata {
+                title: Some("BATCH-001".to_string()),
+                ..Default::default()
+            },
+            MerkleTreeBatchSummary {
+                tree_version: TreeVersion::Rfc6962,
+                state_root: Some(state_root.clone()),
+                ..merkle_summary("BATCH-001", root, 3)
+            },
+        );
+        
+        let stored = contract.get_merkle_batch_summary("BATCH-001".to_string()).unwrap();
+        assert_eq!(stored.s3_prefix, "testdb/BATCH-001/");
+        assert_eq!(stored.batch_summary.start_timestamp, 1_700_000_000_000);
+        assert_eq!(stored.anchoring_data.block_height, 42);
+        assert_eq!(stored.anchoring_data.etrap_fee, "0");
+        
+        let summary = contract.get_batch_summary("BATCH-001".to_string()).unwrap();
+        assert_eq!(summary.timestamp, 1_700_000_060_000);
+        assert_eq!(summary.hash_algorithm, Some(HashAlgorithm::Sha256));
+        assert_eq!(summary.tree_version, TreeVersion::Rfc6962);
+        assert_eq!(summary.state_root, Some(state_root));
+        
+        let proof = contract.generate_merkle_proof(
+            transactions.clone(), 2, true, Some(TreeVersion::Rfc6962), None, None,
+        );
+        assert!(contract.verify_document_in_batch(
+            "BATCH-001".to_string(), transactions[2].clone(), proof, 2, None, None,
+        ));
+    }
+    
+    #[test]
+    #[should_panic(expected = "batch_id must equal token_id")]
+    fn mint_merkle_batch_rejects_a_batch_id_other_than_the_token_id() {
+        let mut contract = setup_contract();
+        set_caller(accounts(0), NearToken::from_near(1));
+        contract.mint_merkle_batch(
+            "BATCH-001".to_string(),
+            accounts(0),
+            TokenMetadata::default(),
+            merkle_summary("BATCH-002", "a".repeat(64), 1),
+        );
+    }
+    
+    #[test]
+    fn detailed_verification_reports_the_minting_block() {
+        let mut contract = setup_contract();
+        let transactions: Vec<String> = (0..3).map(|i| format!("tx-{}", i)).collect();
+        let root = contract.compute_merkle_root(transactions.clone(), true, None, None, None);
+        let proof = contract.generate_merkle_proof(transactions.clone(), 1, true, None, None, None);
+        
+        let context = VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_near(1))
+            .block_timestamp(1_750_000_000_000_000_000)
+            .block_height(1234)
+            .build();
+        testing_env!(context);
+        
+        // The summary's own timestamp is supplied by the minter and is not the anchoring time
+        let mut summary = batch_summary(root, TreeVersion::Legacy, 3);
+        summary.timestamp = 1_000;
+        contract.mint_batch(
+            "anchored".to_string(),
+            accounts(0),
+            TokenMetadata {
+                title: Some("anchored".to_string()),
+                ..Default::default()
+            },
+            summary,
+        );
+        
+        reset_context();
+        let result = contract.verify_document_detailed(
+            "anchored".to_string(), transactions[1].clone(), proof, 1, None, None,
+        );
+        assert!(result.valid);
+        assert_eq!(result.timestamp, Some(1_750_000_000_000_000_000));
+        assert_eq!(result.block_height, Some(1234));
+    }
+    
+    // One-transaction batch of `database_name` whose root is derived from the token ID
+    fn simple_summary(token_id: &str, database_name: &str) -> BatchSummary {
+        BatchSummary {
+            database_name: database_name.to_string(),
+            ..batch_summary(format!("root-{}", token_id), TreeVersion::Legacy, 1)
+        }
+    }
+    
+    // Links the summary to its database's current head and mints it as
+    // `minter`, attaching enough to cover storage and fees
+    fn mint_linked(contract: &mut ETRAPContract, minter: AccountId, token_id: &str, summary: BatchSummary) -> Token {
+        set_caller(minter, NearToken::from_near(1));
+        let mut summary = summary;
+        summary.previous_batch_id = contract.get_database_head(summary.database_name.clone());
+        summary.previous_root = summary.previous_batch_id.clone()
+            .map(|head| contract.get_batch_summary(head).unwrap().merkle_root);
+        contract.mint_batch(
+            token_id.to_string(),
+            accounts(0),
+            TokenMetadata {
+                title: Some(token_id.to_string()),
+                ..Default::default()
+            },
+            summary,
+        )
+    }
+    
+    #[test]
+    fn mmr_proves_every_batch_of_a_database() {
+        let mut contract = setup_contract();
+        for i in 0..7 {
+            let token_id = format!("a-{}", i);
+            mint_linked(&mut contract, accounts(0), &token_id, simple_summary(&token_id, "db_a"));
+        }
+        mint_linked(&mut contract, accounts(0), "b-0", simple_summary("b-0", "db_b"));
+        
+        let mmr_a = contract.get_database_mmr("db_a".to_string()).unwrap();
+        assert_eq!(mmr_a.leaf_count, 7);
+        assert_eq!(mmr_a.peaks.len(), 3);
+        assert_eq!(contract.get_database_mmr("db_b".to_string()).unwrap().leaf_count, 1);
+        assert!(contract.get_database_mmr("db_c".to_string()).is_none());
+        
+        for i in 0..7u64 {
+            let token_id = format!("a-{}", i);
+            let proof = contract.get_mmr_inclusion_proof(token_id.clone()).unwrap();
+            assert_eq!(proof.leaf_index, i);
+            assert_eq!(proof.root, mmr_a.root);
+            assert_eq!(proof.leaf, bytes_to_hex(&mmr::leaf_hash(&token_id, &format!("root-{}", token_id))));
+            
+            let siblings: Vec<Vec<u8>> = proof.siblings.iter()
+                .map(|sibling| merkle::hex_to_bytes(sibling).unwrap())
+                .collect();
+            let peak = mmr::root_from_path(merkle::hex_to_bytes(&proof.leaf).unwrap(), i, &siblings);
+            assert_eq!(bytes_to_hex(&peak), proof.peaks[proof.peak_index as usize]);
+        }
+    }
+    
+    #[test]
+    fn consistency_proofs_link_saved_snapshots() {
+        let mut contract = setup_contract();
+        let mut snapshots = vec![];
+        for i in 0..6 {
+            let token_id = format!("a-{}", i);
+            mint_linked(&mut contract, accounts(0), &token_id, simple_summary(&token_id, "db_a"));
+            let mmr = contract.get_database_mmr("db_a".to_string()).unwrap();
+            snapshots.push((mmr.leaf_count, mmr.root));
+        }
+        
+        let (old_size, old_root) = snapshots[2].clone();
+        let (new_size, new_root) = snapshots[5].clone();
+        let proof = || contract.get_consistency_proof("db_a".to_string(), old_size, new_size).unwrap();
+        
+        assert!(contract.verify_consistency(old_size, old_root.clone(), new_size, new_root.clone(), proof()));
+        assert!(!contract.verify_consistency(
+            old_size, snapshots[1].1.clone(), new_size, new_root.clone(), proof(),
+        ));
+        
+        let mut tampered = proof();
+        tampered.old_peaks[0] = bytes_to_hex(&[0u8; 32]);
+        assert!(!contract.verify_consistency(old_size, old_root, new_size, new_root, tampered));
+        
+        assert!(contract.get_consistency_proof("db_a".to_string(), 0, 3).is_none());
+        assert!(contract.get_consistency_proof("db_a".to_string(), 4, 3).is_none());
+        assert!(contract.get_consistency_proof("db_a".to_string(), 3, 7).is_none());
+    }
+    
+    #[test]
+    fn batch_chain_links_every_batch_of_a_database() {
+        let mut contract = setup_contract();
+        for i in 0..4 {
+            let token_id = format!("a-{}", i);
+            mint_linked(&mut contract, accounts(0), &token_id, simple_summary(&token_id, "db_a"));
+            let token_id = format!("b-{}", i);
+            mint_linked(&mut contract, accounts(0), &token_id, simple_summary(&token_id, "db_b"));
+        }
+        assert_eq!(contract.get_database_head("db_a".to_string()), Some("a-3".to_string()));
+        
+        let result = contract.verify_batch_chain("db_a".to_string(), None, None);
+        assert_eq!(result.batches_checked, 4);
+        assert!(result.breaks.is_empty());
+        assert!(result.complete);
+        
+        let partial = contract.verify_batch_chain("db_a".to_string(), None, Some(2));
+        assert_eq!(partial.next_token_id, Some("a-1".to_string()));
+        assert!(!partial.complete);
+        
+        // Simulate a rewritten link in stored data
+        let mut tampered = contract.get_batch_summary("a-2".to_string()).unwrap();
+        tampered.previous_batch_id = Some("a-0".to_string());
+        tampered.previous_root = Some("root-a-0".to_string());
+        contract.batch_summaries.insert("a-2".to_string(), tampered.into());
+        let result = contract.verify_batch_chain("db_a".to_string(), None, None);
+        assert_eq!(result.breaks.len(), 1);
+        assert_eq!(result.breaks[0].token_id, "a-2");
+        assert_eq!(result.breaks[0].kind, ChainBreakKind::Gap);
+    }
+    
+    #[test]
+    #[should_panic(expected = "previous_batch_id must be the database's current head: a-1")]
+    fn mint_rejects_a_batch_that_skips_the_head() {
+        let mut contract = setup_contract();
+        mint_linked(&mut contract, accounts(0), "a-0", simple_summary("a-0", "db_a"));
+        mint_linked(&mut contract, accounts(0), "a-1", simple_summary("a-1", "db_a"));
+        
+        let mut summary = batch_summary("root-a-2".to_string(), TreeVersion::Legacy, 1);
+        summary.database_name = "db_a".to_string();
+        summary.previous_batch_id = Some("a-0".to_string());
+        summary.previous_root = Some("root-a-0".to_string());
+        contract.mint_batch(
+            "a-2".to_string(),
+            accounts(0),
+            TokenMetadata::default(),
+            summary,
+        );
+    }
+    
+    fn mint_args(token_id: &str, summary: BatchSummary) -> MintArgs {
+        MintArgs {
+            token_id: token_id.to_string(),
+            receiver_id: accounts(0),
+            token_metadata: TokenMetadata {
+                title: Some(token_id.to_string()),
+                ..Default::default()
+            },
+            batch_summary: summary,
+        }
+    }
+    
+    // The second batch of a database in one call links to the first
+    fn chained_summaries(database_name: &str) -> (BatchSummary, BatchSummary) {
+        let first = simple_summary("a-0", database_name);
+        let mut second = simple_summary("a-1", database_name);
+        second.previous_batch_id = Some("a-0".to_string());
+        second.previous_root = Some(first.merkle_root.clone());
+        (first, second)
+    }
+    
+    #[test]
+    fn mint_batches_emits_one_event_and_settles_once() {
+        let mut contract = setup_contract();
+        let fee = NearToken::from_yoctonear(1_000);
+        contract.set_fee(U128(fee.as_yoctonear()));
+        
+        // Persist the setup as the end of a transaction would, so the
+        // storage of the call alone can be measured
+        env::state_write(&contract);
+        drop(contract);
+        set_caller(accounts(0), NearToken::from_near(1));
+        let mut contract: ETRAPContract = env::state_read().unwrap();
+        let initial_storage_usage = env::storage_usage();
+        
+        let (first, second) = chained_summaries("testdb");
+        let tokens = contract.mint_batches(vec![
+            mint_args("a-0", first),
+            mint_args("a-1", second),
+            mint_args("b-0", simple_summary("b-0", "otherdb")),
+        ]);
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(contract.get_database_head("testdb".to_string()), Some("a-1".to_string()));
+        
+        let events: Vec<serde_json::Value> = near_sdk::test_utils::get_logs().iter()
+            .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+            .map(|event| serde_json::from_str(event).unwrap())
+            .collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["event"], "nft_mint");
+        let token_ids: Vec<&str> = events[0]["data"].as_array().unwrap().iter()
+            .map(|entry| entry["token_ids"][0].as_str().unwrap())
+            .collect();
+        assert_eq!(token_ids, ["a-0", "a-1", "b-0"]);
+        
+        // One fee per batch, escrowed together
+        let fees = fee.saturating_mul(3);
+        assert_eq!(contract.get_fee_balance(accounts(1)).available, fees);
+        
+        // A single refund covers the storage of every batch and the fees
+        let receipts = near_sdk::mock::with_mocked_blockchain(|blockchain| blockchain.created_receipts());
+        assert_eq!(receipts.len(), 1);
+        let refund = match receipts[0].actions.as_slice() {
+            [near_sdk::mock::MockAction::Transfer { deposit, .. }] => *deposit,
+            _ => panic!("Expected a single refund transfer"),
+        };
+        env::state_write(&contract);
+        drop(contract);
+        let storage_used = env::storage_usage() - initial_storage_usage;
+        let storage_cost = env::storage_byte_cost().saturating_mul(storage_used as u128);
+        assert_eq!(refund, NearToken::from_near(1).saturating_sub(storage_cost).saturating_sub(fees));
+    }
+    
+    #[test]
+    fn a_bad_batch_reverts_the_whole_mint_batches_call() {
+        let contract = setup_contract();
+        env::state_write(&contract);
+        drop(contract);
+        
+        // Keep the committed storage to put back when the call panics, as the
+        // runtime reverts a failed transaction
+        let committed = near_sdk::mock::with_mocked_blockchain(|blockchain| blockchain.take_storage());
+        let restore = |storage| {
+            let context = VMContextBuilder::new()
+                .current_account_id(accounts(0))
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(NearToken::from_near(1))
+                .build();
+            env::set_blockchain_interface(near_sdk::MockedBlockchain::new(
+                context,
+                near_sdk::test_vm_config(),
+                near_sdk::RuntimeFeesConfig::test(),
+                vec![],
+                storage,
+                Default::default(),
+                None,
+            ));
+        };
+        restore(committed.clone());
+        let mut contract: ETRAPContract = env::state_read().unwrap();
+        
+        let (first, mut second) = chained_summaries("testdb");
+        second.hash_algorithm = None;
+        let minted = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.mint_batches(vec![mint_args("a-0", first), mint_args("a-1", second)]);
+        }));
+        assert!(minted.is_err());
+        // The bad batch comes after the first one was minted
+        assert!(contract.nft_token("a-0".to_string()).is_some());
+        
+        drop(contract);
+        restore(committed);
+        let contract: ETRAPContract = env::state_read().unwrap();
+        assert_eq!(contract.nft_total_supply(), U128(0));
+        assert!(contract.nft_token("a-0".to_string()).is_none());
+        assert!(contract.get_batch_summary("a-0".to_string()).is_none());
+        assert!(contract.get_database_head("testdb".to_string()).is_none());
+    }
+    
+    #[test]
+    #[should_panic(expected = "previous_batch_id must be the database's current head: a-0")]
+    fn mint_batches_requires_batches_of_a_database_to_chain() {
+        let mut contract = setup_contract();
+        set_caller(accounts(0), NearToken::from_near(1));
+        contract.mint_batches(vec![
+            mint_args("a-0", simple_summary("a-0", "testdb")),
+            mint_args("a-1", simple_summary("a-1", "testdb")),
+        ]);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Merkle root must be lowercase hex")]
+    fn mint_rejects_an_uppercase_merkle_root() {
+        let mut contract = setup_contract();
+        let transactions: Vec<String> = (0..3).map(|i| format!("tx-{}", i)).collect();
+        let root = contract.compute_merkle_root(transactions, true, None, None, None);
+        let summary = batch_summary(root.to_uppercase(), TreeVersion::Legacy, 3);
+        mint_linked(&mut contract, accounts(0), "batch", summary);
+    }
+    
+    #[test]
+    fn keccak256_batches_verify_in_every_tree_version() {
+        let mut contract = setup_contract();
+        let transactions: Vec<String> = (0..5).map(|i| format!("tx-{}", i)).collect();
+        for tree_version in [TreeVersion::Legacy, TreeVersion::Rfc6962, TreeVersion::Binary] {
+            reset_context();
+            let root = contract.compute_merkle_root(
+                transactions.clone(), true, Some(tree_version), Some(HashAlgorithm::Keccak256), None,
+            );
+            let sha256_root = contract.compute_merkle_root(transactions.clone(), true, Some(tree_version), None, None);
+            assert_ne!(root, sha256_root);
+            
+            let token_id = format!("keccak-{:?}", tree_version);
+            let summary = BatchSummary {
+                hash_algorithm: Some(HashAlgorithm::Keccak256),
+                ..batch_summary(root, tree_version, 5)
+            };
+            mint_linked(&mut contract, accounts(0), &token_id, summary);
             
-            tree.push(next_level.clone());
-            current_level = next_level;
+            for index in [0, 2, 4] {
+                reset_context();
+                let proof = contract.generate_merkle_proof(
+                    transactions.clone(), index, true, Some(tree_version), Some(HashAlgorithm::Keccak256), None,
+                );
+                let result = contract.verify_document_detailed(
+                    token_id.clone(), transactions[index as usize].clone(), proof, index, None, None,
+                );
+                assert!(result.valid, "{} leaf {}", token_id, index);
+                assert_eq!(result.hash_algorithm, Some(HashAlgorithm::Keccak256));
+            }
         }
+    }
+    
+    #[test]
+    #[should_panic(expected = "tx_count 5 does not match leaf_count 6")]
+    fn mint_rejects_tx_count_that_differs_from_leaf_count() {
+        let mut contract = setup_contract();
+        let mut summary = batch_summary("root".to_string(), TreeVersion::Legacy, 5);
+        summary.leaf_count = Some(6);
+        contract.mint_batch(
+            "batch".to_string(),
+            accounts(0),
+            TokenMetadata {
+                title: Some("batch".to_string()),
+                ..Default::default()
+            },
+            summary,
+        );
+    }
+    
+    #[test]
+    fn mint_charges_the_storage_it_actually_uses() {
+        let mut contract = setup_contract();
+        contract.set_fee(U128(1_000));
+        
+        let context = VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_near(1))
+            .build();
+        testing_env!(context);
+        contract.storage_deposit(None, None);
+        
+        // Persist the setup as the end of a transaction would, then mint
+        // without a deposit so the whole cost comes from the prepaid balance
+        env::state_write(&contract);
+        drop(contract);
+        reset_context();
+        let mut contract: ETRAPContract = env::state_read().unwrap();
+        let initial_storage_usage = env::storage_usage();
+        let prepaid = contract.storage_balance_of(accounts(0)).unwrap().available;
+        
+        contract.mint_batch(
+            "batch".to_string(),
+            accounts(0),
+            TokenMetadata {
+                title: Some("batch".to_string()),
+                ..Default::default()
+            },
+            batch_summary("root".to_string(), TreeVersion::Legacy, 1),
+        );
+        let charged = prepaid
+            .saturating_sub(contract.storage_balance_of(accounts(0)).unwrap().available)
+            .saturating_sub(NearToken::from_yoctonear(1_000));
+        
+        env::state_write(&contract);
+        drop(contract);
+        let storage_used = env::storage_usage() - initial_storage_usage;
+        assert!(storage_used > 0);
+        assert_eq!(charged, env::storage_byte_cost().saturating_mul(storage_used as u128));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Caller does not have the Minter role")]
+    fn mint_requires_the_minter_role() {
+        let mut contract = setup_contract();
+        mint_linked(&mut contract, accounts(2), "batch", simple_summary("batch", "testdb"));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Caller does not have the Minter role")]
+    fn revoking_a_role_takes_effect_immediately() {
+        let mut contract = setup_contract();
+        assert!(contract.grant_role(Role::Minter, accounts(2)));
+        mint_linked(&mut contract, accounts(2), "batch-1", simple_summary("batch-1", "testdb"));
+        assert!(contract.get_batch_summary("batch-1".to_string()).is_some());
+        
+        reset_context();
+        assert!(contract.revoke_role(Role::Minter, accounts(2)));
+        assert!(!contract.has_role(Role::Minter, accounts(2)));
+        mint_linked(&mut contract, accounts(2), "batch-2", simple_summary("batch-2", "testdb"));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn admin_cannot_revoke_another_admin() {
+        let mut contract = setup_contract();
+        contract.grant_role(Role::Admin, accounts(2));
+        contract.grant_role(Role::Admin, accounts(3));
+        
+        set_caller(accounts(2), NearToken::from_yoctonear(0));
+        contract.revoke_role(Role::Admin, accounts(3));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Only the pending owner can accept ownership")]
+    fn only_the_pending_owner_can_accept_ownership() {
+        let mut contract = setup_contract();
+        contract.propose_owner(Some(accounts(2)));
+        
+        set_caller(accounts(3), NearToken::from_yoctonear(0));
+        contract.accept_ownership();
+    }
+    
+    #[test]
+    fn a_new_proposal_replaces_the_pending_owner() {
+        let mut contract = setup_contract();
+        contract.propose_owner(Some(accounts(2)));
+        contract.propose_owner(Some(accounts(3)));
+        assert_eq!(contract.get_pending_owner(), Some(accounts(3)));
+        
+        set_caller(accounts(2), NearToken::from_yoctonear(0));
+        let replaced = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.accept_ownership();
+        }));
+        assert!(replaced.is_err());
+        
+        set_caller(accounts(3), NearToken::from_yoctonear(0));
+        contract.accept_ownership();
+        assert_eq!(contract.get_owner(), Some(accounts(3)));
+        assert_eq!(contract.get_pending_owner(), None);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Threshold must be at least 1 when approvers are set")]
+    fn approval_config_rejects_a_zero_threshold_with_approvers() {
+        let mut contract = setup_contract();
+        contract.set_approval_config(ApprovalConfig {
+            approvers: vec![accounts(2), accounts(3)],
+            threshold: 0,
+            proposal_duration_ns: DEFAULT_PROPOSAL_DURATION_NS,
+        });
+    }
+    
+    #[test]
+    fn sensitive_actions_wait_for_the_approval_threshold() {
+        let mut contract = setup_contract();
+        contract.set_approval_config(ApprovalConfig {
+            approvers: vec![accounts(2), accounts(3), accounts(4)],
+            threshold: 2,
+            proposal_duration_ns: DEFAULT_PROPOSAL_DURATION_NS,
+        });
+        
+        let direct = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.update_treasury(accounts(5));
+        }));
+        assert!(direct.is_err());
         
-        // Generate proof
-        let mut proof = vec![];
-        let mut current_index = tx_index;
+        set_caller(accounts(2), NearToken::from_yoctonear(0));
+        let id = contract.create_proposal(AdminAction::UpdateTreasury { new_treasury: accounts(5) });
+        let early = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.execute_proposal(id);
+        }));
+        assert!(early.is_err());
+        assert_eq!(contract.etrap_settings.fee_recipients[0].account_id, accounts(1));
+        
+        set_caller(accounts(3), NearToken::from_yoctonear(0));
+        contract.approve_proposal(id);
+        contract.execute_proposal(id);
+        assert_eq!(contract.etrap_settings.fee_recipients[0].account_id, accounts(5));
+        assert!(contract.get_proposal(id).is_none());
+    }
+    
+    fn proposal_context(account_id: AccountId, timestamp: u64) {
+        let context = VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(account_id)
+            .block_timestamp(timestamp)
+            .build();
+        testing_env!(context);
+    }
+    
+    fn threshold_contract() -> ETRAPContract {
+        let mut contract = setup_contract();
+        contract.set_approval_config(ApprovalConfig {
+            approvers: vec![accounts(2), accounts(3), accounts(4)],
+            threshold: 2,
+            proposal_duration_ns: DEFAULT_PROPOSAL_DURATION_NS,
+        });
+        contract
+    }
+    
+    #[test]
+    fn expired_proposals_cannot_be_approved_or_executed() {
+        let mut contract = threshold_contract();
+        proposal_context(accounts(2), 0);
+        let id = contract.create_proposal(AdminAction::UpdateTreasury { new_treasury: accounts(5) });
+        
+        proposal_context(accounts(3), DEFAULT_PROPOSAL_DURATION_NS);
+        let approve = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.approve_proposal(id);
+        }));
+        assert_eq!(panic_message(approve), "Proposal has expired");
+        
+        // Approved in time, but executed too late
+        proposal_context(accounts(3), DEFAULT_PROPOSAL_DURATION_NS - 1);
+        contract.approve_proposal(id);
+        proposal_context(accounts(3), DEFAULT_PROPOSAL_DURATION_NS);
+        let execute = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.execute_proposal(id);
+        }));
+        assert_eq!(panic_message(execute), "Proposal has expired");
+        assert_eq!(contract.etrap_settings.fee_recipients[0].account_id, accounts(1));
+        assert!(contract.get_pending_proposals(None, None).is_empty());
+        
+        contract.expire_proposal(id);
+        assert!(contract.get_proposal(id).is_none());
+    }
+    
+    #[test]
+    fn approvals_from_removed_approvers_no_longer_count() {
+        let mut contract = threshold_contract();
+        set_caller(accounts(2), NearToken::from_yoctonear(0));
+        let id = contract.create_proposal(AdminAction::UpdateTreasury { new_treasury: accounts(5) });
+        set_caller(accounts(3), NearToken::from_yoctonear(0));
+        contract.approve_proposal(id);
+        
+        // Replace accounts(3) with accounts(5) before the treasury proposal runs
+        let config_id = contract.create_proposal(AdminAction::SetApprovalConfig {
+            config: ApprovalConfig {
+                approvers: vec![accounts(2), accounts(4), accounts(5)],
+                threshold: 2,
+                proposal_duration_ns: DEFAULT_PROPOSAL_DURATION_NS,
+            },
+        });
+        set_caller(accounts(4), NearToken::from_yoctonear(0));
+        contract.approve_proposal(config_id);
+        contract.execute_proposal(config_id);
         
-        for level in 0..(tree.len() - 1) {
-            let level_size = tree[level].len() as u32;
-            
-            // Determine sibling index
-            let sibling_index = if current_index % 2 == 0 {
-                current_index + 1
-            } else {
-                current_index - 1
-            };
-            
-            // Add sibling to proof if it exists
-            if sibling_index < level_size {
-                proof.push(tree[level][sibling_index as usize].clone());
-            }
-            
-            // Move to parent index
-            current_index /= 2;
-        }
+        set_caller(accounts(2), NearToken::from_yoctonear(0));
+        let execute = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.execute_proposal(id);
+        }));
+        assert_eq!(panic_message(execute), "Not enough approvals: 1 of 2");
+        assert_eq!(contract.etrap_settings.fee_recipients[0].account_id, accounts(1));
         
-        proof
+        set_caller(accounts(5), NearToken::from_yoctonear(0));
+        contract.approve_proposal(id);
+        contract.execute_proposal(id);
+        assert_eq!(contract.etrap_settings.fee_recipients[0].account_id, accounts(5));
     }
     
-    // Get recent batches
-    pub fn get_recent_batches(&self, limit: Option<u64>) -> Vec<BatchInfo> {
-        let limit = limit.unwrap_or(20).min(RECENT_TOKENS_LIMIT) as usize;
-        let start = self.recent_tokens.len().saturating_sub(limit as u32);
+    #[test]
+    fn proposals_with_invalid_payloads_are_rejected_at_creation() {
+        let mut contract = threshold_contract();
+        set_caller(accounts(2), NearToken::from_yoctonear(0));
         
-        (start..self.recent_tokens.len())
-            .rev()
-            .map(|i| {
-                let token_id = self.recent_tokens.get(i as u32).unwrap();
-                self.get_batch_info(token_id)
-            })
-            .collect()
+        let config = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.create_proposal(AdminAction::SetApprovalConfig {
+                config: ApprovalConfig {
+                    approvers: vec![accounts(2)],
+                    threshold: 2,
+                    proposal_duration_ns: DEFAULT_PROPOSAL_DURATION_NS,
+                },
+            });
+        }));
+        assert_eq!(panic_message(config), "Threshold cannot exceed the number of approvers");
+        
+        let recipients = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.create_proposal(AdminAction::SetFeeRecipients {
+                recipients: vec![recipient(accounts(1), 5_000), recipient(accounts(5), 4_000)],
+            });
+        }));
+        assert_eq!(panic_message(recipients), "Fee recipient shares must sum to 10000 basis points");
+        
+        assert!(contract.get_pending_proposals(None, None).is_empty());
     }
     
-    // Search by database with pagination
-    pub fn get_batches_by_database(
-        &self,
-        database: String,
-        from_index: Option<u64>,
-        limit: Option<u64>,
-    ) -> BatchSearchResult {
-        let limit = limit.unwrap_or(50).min(100) as usize;
-        let from_index = from_index.unwrap_or(0) as usize;
+    #[test]
+    fn storage_deposits_can_be_withdrawn_down_to_the_minimum() {
+        let mut contract = setup_contract();
+        let min_balance = contract.storage_balance_bounds().min;
         
-        let db_tokens = match self.tokens_by_database.get(&database) {
-            Some(tokens) => tokens,
-            None => {
-                return BatchSearchResult {
-                    batches: vec![],
-                    total_count: 0,
-                    has_more: false,
-                };
-            }
-        };
+        set_caller(accounts(2), NearToken::from_near(1));
+        let balance = contract.storage_deposit(None, None);
+        assert_eq!(balance.total, NearToken::from_near(1));
+        assert_eq!(balance.available, NearToken::from_near(1).saturating_sub(min_balance));
         
-        let total = db_tokens.len() as u64;
-        let tokens: Vec<BatchInfo> = db_tokens
-            .iter()
-            .skip(from_index)
-            .take(limit)
-            .map(|token_id| self.get_batch_info(token_id))
-            .collect();
+        set_caller(accounts(2), NearToken::from_yoctonear(1));
+        let balance = contract.storage_withdraw(Some(NearToken::from_millinear(400)));
+        assert_eq!(balance.total, NearToken::from_millinear(600));
         
-        BatchSearchResult {
-            batches: tokens,
-            total_count: total,
-            has_more: from_index + limit < total as usize,
-        }
+        let overdraw = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.storage_withdraw(Some(NearToken::from_millinear(600)));
+        }));
+        assert!(overdraw.is_err());
+        
+        let balance = contract.storage_withdraw(None);
+        assert_eq!(balance.total, min_balance);
+        assert_eq!(balance.available, NearToken::from_yoctonear(0));
     }
     
-    // Time range query
-    pub fn get_batches_by_time_range(
-        &self,
-        start_timestamp: u64,
-        end_timestamp: u64,
-        database: Option<String>,
-        limit: Option<u64>,
-    ) -> Vec<BatchInfo> {
-        let limit = limit.unwrap_or(100).min(1000) as usize;
+    #[test]
+    fn prepaid_storage_covers_a_deposit_shortfall() {
+        let mut contract = setup_contract();
+        set_caller(accounts(0), NearToken::from_near(1));
+        contract.storage_deposit(None, None);
+        let prepaid = contract.storage_balance_of(accounts(0)).unwrap().available;
         
-        self.tokens_by_timestamp
-            .iter()
-            .filter(|(ts, _)| **ts >= start_timestamp && **ts <= end_timestamp)
-            .filter_map(|(_, token_id)| {
-                let summary = self.batch_summaries.get(token_id)?;
-                
-                // Apply database filter if provided
-                if let Some(ref db) = database {
-                    if &summary.database_name != db {
-                        return None;
-                    }
-                }
-                
-                Some(self.get_batch_info(token_id))
-            })
-            .take(limit)
-            .collect()
+        set_caller(accounts(0), NearToken::from_yoctonear(1));
+        contract.mint_batch(
+            "batch".to_string(),
+            accounts(0),
+            TokenMetadata::default(),
+            batch_summary("root".to_string(), TreeVersion::Legacy, 1),
+        );
+        
+        let remaining = contract.storage_balance_of(accounts(0)).unwrap().available;
+        assert!(remaining < prepaid);
+        assert!(remaining > NearToken::from_yoctonear(0));
     }
     
-    // Get batches by table
-    pub fn get_batches_by_table(
-        &self,
-        table_name: String,
-        limit: Option<u64>,
-    ) -> Vec<BatchInfo> {
-        let limit = limit.unwrap_or(50).min(100) as usize;
+    #[test]
+    #[should_panic(expected = "Insufficient deposit")]
+    fn mint_fails_when_deposit_and_prepaid_storage_fall_short() {
+        let mut contract = setup_contract();
+        set_caller(accounts(0), contract.storage_balance_bounds().min);
+        contract.storage_deposit(None, Some(true));
         
-        self.tokens_by_table
-            .get(&table_name)
-            .map(|tokens| {
-                tokens.iter()
-                    .take(limit)
-                    .map(|token_id| self.get_batch_info(token_id))
-                    .collect()
-            })
-            .unwrap_or_default()
+        set_caller(accounts(0), NearToken::from_yoctonear(1));
+        contract.mint_batch(
+            "batch".to_string(),
+            accounts(0),
+            TokenMetadata::default(),
+            batch_summary("root".to_string(), TreeVersion::Legacy, 1),
+        );
     }
     
-    // Get batch statistics
-    pub fn get_batch_stats(&self, token_id: Option<TokenId>) -> serde_json::Value {
-        if let Some(tid) = token_id {
-            // Stats for specific batch
-            let summary = self.batch_summaries.get(&tid)
-                .expect("Batch not found");
-            
-            json!({
-                "token_id": tid,
-                "database": summary.database_name,
-                "transaction_count": summary.tx_count,
-                "merkle_root": summary.merkle_root,
-                "timestamp": summary.timestamp,
-                "operations": {
-                    "inserts": summary.operation_counts.inserts,
-                    "updates": summary.operation_counts.updates,
-                    "deletes": summary.operation_counts.deletes,
-                }
-            })
-        } else {
-            // Global stats
-            let total_batches = self.tokens.nft_total_supply();
-            let total_databases = self.database_list.len();
-            
-            json!({
-                "total_batches": total_batches,
-                "total_databases": total_databases,
-                "databases": self.database_list.iter().cloned().collect::<Vec<_>>(),
-            })
+    #[test]
+    fn fee_amounts_parse_to_exact_yocto() {
+        reset_context();
+        assert_eq!(ETRAPContract::parse_near_amount("0.01"), 10u128.pow(22));
+        assert_eq!(ETRAPContract::near_amount_to_yocto(&NearAmount::Number(0.01)), 10u128.pow(22));
+        assert_eq!(ETRAPContract::parse_near_amount("1"), YOCTO_PER_NEAR);
+        assert_eq!(ETRAPContract::parse_near_amount(".5"), YOCTO_PER_NEAR / 2);
+        assert_eq!(ETRAPContract::parse_near_amount("0.000000000000000000000001"), 1);
+        
+        let contract = ETRAPContract::new(
+            accounts(0),
+            "Test Organization".to_string(),
+            accounts(1),
+            NearAmount::Decimal("0.01".to_string()),
+        );
+        assert_eq!(contract.etrap_settings.fee_amount, NearToken::from_yoctonear(10u128.pow(22)));
+    }
+    
+    #[test]
+    fn malformed_fee_amounts_are_rejected() {
+        reset_context();
+        let malformed = ["", ".", "abc", "1.2.3", "-1", "+1", "1e5", "0,01", "1 000", "0.0000000000000000000000001"];
+        for amount in malformed {
+            let parsed = std::panic::catch_unwind(|| ETRAPContract::parse_near_amount(amount));
+            assert!(parsed.is_err(), "{:?} was accepted", amount);
+        }
+        for number in [-0.5, f64::NAN, f64::INFINITY] {
+            let parsed = std::panic::catch_unwind(|| {
+                ETRAPContract::near_amount_to_yocto(&NearAmount::Number(number))
+            });
+            assert!(parsed.is_err(), "{} was accepted", number);
         }
     }
     
-    // Get databases list
-    pub fn get_databases(&self) -> Vec<String> {
-        self.database_list.iter().cloned().collect()
+    #[test]
+    fn year_months_follow_the_calendar() {
+        let cases = [
+            (0, "1970-01"),
+            (2_678_400_000 - 1, "1970-01"),
+            (2_678_400_000, "1970-02"),
+            (951_868_800_000 - 1, "2000-02"),
+            (951_868_800_000, "2000-03"),
+            (1_704_067_200_000 - 1, "2023-12"),
+            (1_704_067_200_000, "2024-01"),
+            (1_709_251_200_000 - 1, "2024-02"),
+            (1_709_251_200_000, "2024-03"),
+            (1_764_547_200_000 - 1, "2025-11"),
+            (1_764_547_200_000, "2025-12"),
+            (4_107_542_400_000 - 1, "2100-02"),
+            (4_107_542_400_000, "2100-03"),
+        ];
+        for (timestamp, year_month) in cases {
+            assert_eq!(ETRAPContract::timestamp_to_year_month(timestamp), year_month, "{}", timestamp);
+        }
     }
     
-    // Get batch summary
-    pub fn get_batch_summary(&self, token_id: TokenId) -> Option<BatchSummary> {
-        self.batch_summaries.get(&token_id).cloned()
+    #[test]
+    fn billing_month_uses_the_block_time() {
+        let context = VMContextBuilder::new()
+            .block_timestamp(1_704_067_200_000 * 1_000_000 - 1)
+            .build();
+        testing_env!(context);
+        assert_eq!(ETRAPContract::current_billing_month(), "2023-12");
+        
+        let context = VMContextBuilder::new()
+            .block_timestamp(1_704_067_200_000 * 1_000_000)
+            .build();
+        testing_env!(context);
+        assert_eq!(ETRAPContract::current_billing_month(), "2024-01");
     }
     
-    // Admin functions
+    #[test]
+    fn volume_discounts_apply_within_the_block_month() {
+        let mut contract = setup_contract();
+        contract.set_fee(U128(1_000));
+        contract.set_discount_tiers(vec![
+            DiscountTier { min_batches: 2, discount_bps: 1_000 },
+            DiscountTier { min_batches: 4, discount_bps: 2_500 },
+        ]);
+        let tier_after = |contract: &mut ETRAPContract, minted: u64| {
+            let current = contract.get_volume_tier().batches_this_month;
+            for i in current..minted {
+                let token_id = format!("batch-{}", i);
+                mint_linked(contract, accounts(0), &token_id, simple_summary(&token_id, "testdb"));
+            }
+            reset_context();
+            let tier = contract.get_volume_tier();
+            assert_eq!(tier.batches_this_month, minted);
+            let quote = contract.quote_fee(simple_summary("next", "testdb"));
+            (tier, quote.total_fee.as_yoctonear())
+        };
+        
+        let (tier, fee) = tier_after(&mut contract, 1);
+        assert!(tier.current_tier.is_none());
+        assert_eq!(tier.next_tier.unwrap().min_batches, 2);
+        assert_eq!(tier.batches_until_next_tier, Some(1));
+        assert_eq!(fee, 1_000);
+        
+        let (tier, fee) = tier_after(&mut contract, 2);
+        assert_eq!(tier.current_tier.unwrap().discount_bps, 1_000);
+        assert_eq!(tier.next_tier.unwrap().min_batches, 4);
+        assert_eq!(tier.batches_until_next_tier, Some(2));
+        assert_eq!(fee, 900);
+        
+        let (tier, fee) = tier_after(&mut contract, 4);
+        assert_eq!(tier.current_tier.unwrap().discount_bps, 2_500);
+        assert!(tier.next_tier.is_none());
+        assert_eq!(tier.batches_until_next_tier, None);
+        assert_eq!(fee, 750);
+        
+        let (tier, fee) = tier_after(&mut contract, 5);
+        assert_eq!(tier.current_tier.unwrap().min_batches, 4);
+        assert_eq!(tier.batches_until_next_tier, None);
+        assert_eq!(fee, 750);
+        
+        // Every batch above was minted in January 1970; February starts over
+        let context = VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .block_timestamp(31 * 86_400 * 1_000_000_000)
+            .build();
+        testing_env!(context);
+        let tier = contract.get_volume_tier();
+        assert_eq!(tier.month, "1970-02");
+        assert_eq!(tier.batches_this_month, 0);
+        assert!(tier.current_tier.is_none());
+        assert_eq!(tier.batches_until_next_tier, Some(2));
+        assert_eq!(contract.quote_fee(simple_summary("next", "testdb")).total_fee.as_yoctonear(), 1_000);
+    }
     
-    #[private]
-    pub fn set_paused(&mut self, paused: bool) {
-        self.etrap_settings.paused = paused;
+    // Message a contract call panicked with
+    fn panic_message(result: std::thread::Result<()>) -> String {
+        let payload = result.expect_err("Expected the call to panic");
+        payload.downcast_ref::<String>().cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|message| message.to_string()))
+            .unwrap_or_default()
     }
     
-    // View method to get contract settings
-    pub fn get_settings(&self) -> serde_json::Value {
-        json!({
-            "etrap_treasury": self.etrap_settings.etrap_treasury,
-            "fee_amount": self.etrap_settings.fee_amount.as_yoctonear().to_string(),
-            "paused": self.etrap_settings.paused
-        })
+    fn recipient(account_id: AccountId, share_bps: u16) -> FeeRecipient {
+        FeeRecipient { account_id, share_bps }
     }
     
-    #[private]
-    pub fn update_treasury(&mut self, new_treasury: AccountId) {
-        self.etrap_settings.etrap_treasury = new_treasury;
+    #[test]
+    fn fee_split_gives_the_rounding_dust_to_the_first_recipient() {
+        let mut contract = setup_contract();
+        contract.set_fee(U128(10_001));
+        contract.set_fee_recipients(vec![recipient(accounts(1), 3333), recipient(accounts(2), 6667)]);
+        
+        let quote = contract.quote_fee(simple_summary("batch", "testdb"));
+        let split: Vec<(AccountId, u128)> = quote.split.iter()
+            .map(|share| (share.account_id.clone(), share.amount.as_yoctonear()))
+            .collect();
+        assert_eq!(split, [(accounts(1), 3334), (accounts(2), 6667)]);
+        
+        mint_linked(&mut contract, accounts(0), "batch", simple_summary("batch", "testdb"));
+        let log = near_sdk::test_utils::get_logs().into_iter()
+            .find_map(|log| log.strip_prefix("EVENT_JSON:").map(str::to_string))
+            .unwrap();
+        let event: serde_json::Value = serde_json::from_str(&log).unwrap();
+        let fee_info = &event["data"][0]["fee_info"];
+        assert_eq!(fee_info["etrap_fee"], "10001");
+        assert_eq!(fee_info["split"][0]["account_id"], accounts(1).to_string());
+        assert_eq!(fee_info["split"][0]["amount"], "3334");
+        assert_eq!(fee_info["split"][1]["amount"], "6667");
+        
+        assert_eq!(contract.get_fee_balance(accounts(1)).available, NearToken::from_yoctonear(3334));
+        assert_eq!(contract.get_fee_balance(accounts(2)).available, NearToken::from_yoctonear(6667));
+        assert_eq!(contract.get_fee_totals().accrued, NearToken::from_yoctonear(10_001));
     }
-}
-
-// Implement NFT standard traits
-#[near_bindgen]
-impl NonFungibleTokenCore for ETRAPContract {
-    #[payable]
-    fn nft_transfer(
-        &mut self,
-        receiver_id: AccountId,
-        token_id: TokenId,
-        approval_id: Option<u64>,
-        memo: Option<String>,
-    ) {
-        self.tokens.nft_transfer(receiver_id, token_id, approval_id, memo)
+    
+    #[test]
+    fn fee_recipients_must_be_distinct_positive_shares_of_the_whole() {
+        let mut contract = setup_contract();
+        let mut rejection = |recipients: Vec<FeeRecipient>| panic_message(std::panic::catch_unwind(
+            std::panic::AssertUnwindSafe(|| contract.set_fee_recipients(recipients)),
+        ));
+        
+        assert_eq!(
+            rejection(vec![recipient(accounts(1), 5000), recipient(accounts(2), 4999)]),
+            "Fee recipient shares must sum to 10000 basis points"
+        );
+        assert_eq!(
+            rejection(vec![recipient(accounts(1), 5000), recipient(accounts(1), 5000)]),
+            "Duplicate fee recipient"
+        );
+        assert_eq!(
+            rejection(vec![recipient(accounts(1), 10_000), recipient(accounts(2), 0)]),
+            "Fee recipient shares must be positive"
+        );
+        assert_eq!(rejection(vec![]), "At least one fee recipient is required");
+        
+        reset_context();
+        assert_eq!(contract.get_settings()["fee_recipients"][0]["account_id"], accounts(1).to_string());
     }
-
-    #[payable]
-    fn nft_transfer_call(
-        &mut self,
-        receiver_id: AccountId,
-        token_id: TokenId,
-        approval_id: Option<u64>,
-        memo: Option<String>,
-        msg: String,
-    ) -> PromiseOrValue<bool> {
-        self.tokens.nft_transfer_call(receiver_id, token_id, approval_id, memo, msg)
+    
+    fn pricing(per_transaction_fee: u128, per_byte_fee: u128, min_fee: u128, max_fee: Option<u128>) -> UsagePricing {
+        UsagePricing {
+            per_transaction_fee: NearToken::from_yoctonear(per_transaction_fee),
+            per_byte_fee: NearToken::from_yoctonear(per_byte_fee),
+            min_fee: NearToken::from_yoctonear(min_fee),
+            max_fee: max_fee.map(NearToken::from_yoctonear),
+        }
     }
-
-    fn nft_token(&self, token_id: TokenId) -> Option<Token> {
-        self.tokens.nft_token(token_id)
+    
+    #[test]
+    fn usage_pricing_adds_per_transaction_and_per_byte_fees_within_the_caps() {
+        let mut contract = setup_contract();
+        contract.set_fee(U128(100));
+        let mut summary = simple_summary("batch", "testdb");
+        summary.tx_count = 5;
+        summary.leaf_count = Some(5);
+        summary.size_bytes = 50;
+        
+        contract.set_fee_pricing(pricing(10, 1, 0, None));
+        let quote = contract.quote_fee(summary.clone());
+        assert_eq!(quote.base_fee.as_yoctonear(), 100);
+        assert_eq!(quote.transaction_fee.as_yoctonear(), 50);
+        assert_eq!(quote.size_fee.as_yoctonear(), 50);
+        assert_eq!(quote.subtotal.as_yoctonear(), 200);
+        assert_eq!(quote.total_fee.as_yoctonear(), 200);
+        
+        contract.set_fee_pricing(pricing(10, 1, 1_000, None));
+        let quote = contract.quote_fee(summary.clone());
+        assert_eq!(quote.subtotal.as_yoctonear(), 200);
+        assert_eq!(quote.total_fee.as_yoctonear(), 1_000);
+        
+        contract.set_fee_pricing(pricing(10, 1, 0, Some(150)));
+        let quote = contract.quote_fee(summary.clone());
+        assert_eq!(quote.total_fee.as_yoctonear(), 150);
+        
+        let rejected = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.set_fee_pricing(pricing(0, 0, 200, Some(100)));
+        }));
+        assert_eq!(panic_message(rejected), "Maximum fee cannot be lower than the minimum fee");
+        
+        // mint_batch charges exactly the quote
+        reset_context();
+        let quote = contract.quote_fee(summary.clone());
+        mint_linked(&mut contract, accounts(0), "batch", summary);
+        assert_eq!(contract.get_fee_totals().accrued, quote.total_fee);
+        assert_eq!(contract.get_settings()["pricing"]["max_fee"], "150");
     }
-}
-
-#[near_bindgen]
-impl NonFungibleTokenResolver for ETRAPContract {
-    #[private]
-    fn nft_resolve_transfer(
-        &mut self,
-        previous_owner_id: AccountId,
-        receiver_id: AccountId,
-        token_id: TokenId,
-        approved_account_ids: Option<HashMap<AccountId, u64>>,
-    ) -> bool {
-        self.tokens.nft_resolve_transfer(
-            previous_owner_id,
-            receiver_id,
-            token_id,
-            approved_account_ids,
-        )
+    
+    #[test]
+    fn failed_fee_withdrawal_restores_the_balance() {
+        let mut contract = setup_contract();
+        let fee = NearToken::from_millinear(10);
+        contract.set_fee(U128(fee.as_yoctonear()));
+        mint_linked(&mut contract, accounts(0), "batch", simple_summary("batch", "testdb"));
+        assert_eq!(contract.get_fee_balance(accounts(1)).available, fee);
+        
+        set_caller(accounts(1), NearToken::from_yoctonear(1));
+        contract.withdraw_fees(None);
+        let balance = contract.get_fee_balance(accounts(1));
+        assert_eq!(balance.available, NearToken::from_yoctonear(0));
+        assert_eq!(balance.pending, fee);
+        
+        // The transfer fails and the callback runs with its result
+        testing_env!(
+            VMContextBuilder::new()
+                .current_account_id(accounts(0))
+                .predecessor_account_id(accounts(0))
+                .build(),
+            near_sdk::test_vm_config(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![near_sdk::PromiseResult::Failed],
+        );
+        assert!(!contract.on_fee_withdrawn(accounts(1), U128(fee.as_yoctonear())));
+        
+        let balance = contract.get_fee_balance(accounts(1));
+        assert_eq!(balance.available, fee);
+        assert_eq!(balance.pending, NearToken::from_yoctonear(0));
+        assert_eq!(balance.withdrawn, NearToken::from_yoctonear(0));
+        assert_eq!(contract.get_fee_totals().pending, NearToken::from_yoctonear(0));
     }
-}
-
-#[near_bindgen]
-impl NonFungibleTokenMetadataProvider for ETRAPContract {
-    fn nft_metadata(&self) -> NFTContractMetadata {
-        self.metadata.clone()
+    
+    #[test]
+    #[should_panic(expected = "hash_algorithm is required")]
+    fn mint_requires_a_hash_algorithm() {
+        let mut contract = setup_contract();
+        let mut summary = batch_summary("a".repeat(64), TreeVersion::Legacy, 1);
+        summary.hash_algorithm = None;
+        contract.mint_batch(
+            "batch".to_string(),
+            accounts(0),
+            TokenMetadata::default(),
+            summary,
+        );
     }
 }
-
-#[near_bindgen]
-impl NonFungibleTokenEnumeration for ETRAPContract {
-    fn nft_total_supply(&self) -> U128 {
-        self.tokens.nft_total_supply()
-    }
-
-    fn nft_tokens(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
-        self.tokens.nft_tokens(from_index, limit)
-    }
-
-    fn nft_supply_for_owner(&self, account_id: AccountId) -> U128 {
-        self.tokens.nft_supply_for_owner(account_id)
-    }
-
-    fn nft_tokens_for_owner(
-        &self,
-        account_id: AccountId,
-        from_index: Option<U128>,
-        limit: Option<u64>,
-    ) -> Vec<Token> {
-        self.tokens.nft_tokens_for_owner(account_id, from_index, limit)
-    }
-}
\ No newline at end of file
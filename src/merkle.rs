@@ -0,0 +1,544 @@
+// Merkle tree schemes used to build and verify batch roots.
+//
+// Legacy batches hash lowercase hex strings and use the same function for leaves
+// and internal nodes. The RFC 6962 scheme (as used by Certificate Transparency)
+// hashes raw bytes and prefixes leaves with 0x00 and internal nodes with 0x01,
+// so an internal node can never be presented as a leaf. The binary scheme hashes
+// raw 32-byte digests without prefixes, matching common Merkle libraries.
+//
+// Independently of the tree scheme, a batch names the hash function its nodes
+// are built with. Keccak256 roots can be re-verified cheaply on EVM chains.
+//
+// Callers say how a document is given with a LeafEncoding. The legacy encoding
+// guesses from the document's shape, and the methods that accept it don't all
+// guess alike; it is kept for existing clients only.
+//
+// Copyright (c) 2025 Graziano Labs Corp. All rights reserved.
+
+use near_sdk::base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::env;
+use near_sdk::serde::{Deserialize, Serialize};
+
+pub const LEAF_PREFIX: u8 = 0x00;
+pub const NODE_PREFIX: u8 = 0x01;
+
+// Tree scheme a batch's merkle root was built with
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum TreeVersion {
+    // sha256(left_hex || right_hex) over hex strings, no domain separation
+    #[default]
+    Legacy,
+    // sha256(0x00 || leaf) for leaves, sha256(0x01 || left || right) for nodes
+    Rfc6962,
+    // sha256(left || right) over raw 32-byte digests, leaves used as-is
+    Binary,
+}
+
+// Hash function a batch's merkle root was built with
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    Sha256,
+    Keccak256,
+    // Legacy: plain string concatenation, root stored as "simple_concat:<root>"
+    SimpleConcat,
+    // Legacy: plain string concatenation, root stored as-is
+    Concat,
+}
+
+pub const SIMPLE_CONCAT_PREFIX: &str = "simple_concat:";
+
+// How a document passed to the proof methods is encoded. Documents are UTF-8
+// text unless the caller says otherwise.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum LeafEncoding {
+    // Per-method guessing by length, only when asked for by existing clients
+    Legacy,
+    // An already hashed 32-byte digest, in hex or base64
+    Digest,
+    // A raw document as UTF-8 text, hashed by the tree
+    #[default]
+    Utf8,
+    // A raw document as base64 bytes, hashed by the tree
+    Base64,
+}
+
+impl HashAlgorithm {
+    // Parse a free-form algorithm name such as "SHA256", "sha-256" or "keccak256"
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name: String = name
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        match name.as_str() {
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "keccak256" => Some(HashAlgorithm::Keccak256),
+            "simpleconcat" => Some(HashAlgorithm::SimpleConcat),
+            "concat" => Some(HashAlgorithm::Concat),
+            _ => None,
+        }
+    }
+
+    // Algorithm implied by the shape of a root, for batches that don't name one
+    pub fn infer(merkle_root: &str) -> Self {
+        if merkle_root.starts_with(SIMPLE_CONCAT_PREFIX) {
+            HashAlgorithm::SimpleConcat
+        } else if merkle_root.len() == 64 && merkle_root.chars().all(|c| c.is_ascii_hexdigit()) {
+            HashAlgorithm::Sha256
+        } else {
+            HashAlgorithm::Concat
+        }
+    }
+
+    // Whether nodes are digests rather than concatenated strings
+    pub fn is_digest(&self) -> bool {
+        matches!(self, HashAlgorithm::Sha256 | HashAlgorithm::Keccak256)
+    }
+
+    // Digest of `data`; the concatenation modes don't hash and return it unchanged
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgorithm::Sha256 => env::sha256(data),
+            HashAlgorithm::Keccak256 => env::keccak256(data),
+            HashAlgorithm::SimpleConcat | HashAlgorithm::Concat => data.to_vec(),
+        }
+    }
+}
+
+impl TreeVersion {
+    // Whether nodes are raw bytes rather than hex strings
+    pub fn is_byte_level(&self) -> bool {
+        !matches!(self, TreeVersion::Legacy)
+    }
+}
+
+// Helper function to normalize hash format (remove 0x prefix if present)
+pub fn normalize_hash(hash: &str) -> String {
+    hash.strip_prefix("0x").unwrap_or(hash).to_string()
+}
+
+// Helper function to convert bytes to hex string
+pub fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>()
+}
+
+// Decode a hex string (optionally 0x-prefixed) into bytes
+pub fn hex_to_bytes(hex: &str) -> Option<Vec<u8>> {
+    let hex = normalize_hash(hex);
+    if hex.len() & 1 == 1 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+// Decode a 32-byte digest given as 64 hex characters
+pub fn digest_from_hex(hex: &str) -> Option<Vec<u8>> {
+    hex_to_bytes(hex).filter(|bytes| bytes.len() == 32)
+}
+
+// Decode a 32-byte digest given as 64 hex characters or as base64
+pub fn digest_from_str(digest: &str) -> Option<Vec<u8>> {
+    digest_from_hex(digest).or_else(|| {
+        BASE64.decode(digest.trim()).ok().filter(|bytes| bytes.len() == 32)
+    })
+}
+
+// Data committed to by a leaf: a hex digest is decoded to its 32 bytes,
+// anything else is taken as UTF-8 text
+pub fn leaf_data(document: &str) -> Vec<u8> {
+    digest_from_hex(document).unwrap_or_else(|| document.as_bytes().to_vec())
+}
+
+pub fn rfc6962_leaf_hash(algorithm: HashAlgorithm, data: &[u8]) -> Vec<u8> {
+    let mut input = Vec::with_capacity(data.len() + 1);
+    input.push(LEAF_PREFIX);
+    input.extend_from_slice(data);
+    algorithm.digest(&input)
+}
+
+pub fn rfc6962_node_hash(algorithm: HashAlgorithm, left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut input = Vec::with_capacity(left.len() + right.len() + 1);
+    input.push(NODE_PREFIX);
+    input.extend_from_slice(left);
+    input.extend_from_slice(right);
+    algorithm.digest(&input)
+}
+
+pub fn binary_node_hash(algorithm: HashAlgorithm, left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut input = Vec::with_capacity(left.len() + right.len());
+    input.extend_from_slice(left);
+    input.extend_from_slice(right);
+    algorithm.digest(&input)
+}
+
+// Leaf node of a document in a tree of the given version. Legacy leaves are
+// hex strings; a document that is already a hex digest is used unchanged.
+pub fn leaf_hash(version: TreeVersion, algorithm: HashAlgorithm, document: &str) -> Vec<u8> {
+    match version {
+        TreeVersion::Legacy => {
+            let normalized = normalize_hash(document);
+            if !algorithm.is_digest() {
+                document.as_bytes().to_vec()
+            } else if normalized.len() == 64 && normalized.chars().all(|c| c.is_ascii_hexdigit()) {
+                normalized.into_bytes()
+            } else {
+                bytes_to_hex(&algorithm.digest(document.as_bytes())).into_bytes()
+            }
+        }
+        TreeVersion::Rfc6962 => rfc6962_leaf_hash(algorithm, &leaf_data(document)),
+        TreeVersion::Binary => {
+            digest_from_str(document).unwrap_or_else(|| algorithm.digest(document.as_bytes()))
+        }
+    }
+}
+
+// Leaf node of a document given in an explicit encoding; None for the legacy
+// encoding or a document that doesn't decode. A digest is the document's
+// hash; raw documents are hashed first, except that RFC 6962 leaves hash the
+// raw data with their 0x00 prefix.
+pub fn encoded_leaf_hash(
+    version: TreeVersion,
+    algorithm: HashAlgorithm,
+    encoding: LeafEncoding,
+    document: &str,
+) -> Option<Vec<u8>> {
+    let data = match encoding {
+        LeafEncoding::Legacy => return None,
+        LeafEncoding::Digest => digest_from_str(document)?,
+        LeafEncoding::Utf8 => document.as_bytes().to_vec(),
+        LeafEncoding::Base64 => BASE64.decode(document.trim()).ok()?,
+    };
+    let hashed = |data: Vec<u8>| match encoding {
+        LeafEncoding::Digest => data,
+        _ => algorithm.digest(&data),
+    };
+    Some(match version {
+        TreeVersion::Rfc6962 => rfc6962_leaf_hash(algorithm, &data),
+        TreeVersion::Binary => hashed(data),
+        // Concatenation trees join raw documents, and digests as hex
+        TreeVersion::Legacy if !algorithm.is_digest() && encoding != LeafEncoding::Digest => data,
+        TreeVersion::Legacy => bytes_to_hex(&hashed(data)).into_bytes(),
+    })
+}
+
+// Order of leaves in a sorted tree: the document's digest, so a raw document
+// and its hash sort and compare as the same leaf. Legacy encoding takes a
+// 64-character hex document as a digest and hashes anything else. None if
+// the document doesn't decode.
+pub fn sort_digest(algorithm: HashAlgorithm, encoding: LeafEncoding, document: &str) -> Option<Vec<u8>> {
+    Some(match encoding {
+        LeafEncoding::Legacy => {
+            digest_from_hex(document).unwrap_or_else(|| algorithm.digest(document.as_bytes()))
+        }
+        LeafEncoding::Digest => digest_from_str(document)?,
+        LeafEncoding::Utf8 => algorithm.digest(document.as_bytes()),
+        LeafEncoding::Base64 => algorithm.digest(&BASE64.decode(document.trim()).ok()?),
+    })
+}
+
+// Parent of two nodes in a tree of the given version
+pub fn node_hash(version: TreeVersion, algorithm: HashAlgorithm, left: &[u8], right: &[u8]) -> Vec<u8> {
+    match version {
+        TreeVersion::Legacy if algorithm.is_digest() => {
+            bytes_to_hex(&binary_node_hash(algorithm, left, right)).into_bytes()
+        }
+        TreeVersion::Legacy | TreeVersion::Binary => binary_node_hash(algorithm, left, right),
+        TreeVersion::Rfc6962 => rfc6962_node_hash(algorithm, left, right),
+    }
+}
+
+// Build every level of the tree, leaves first. A node without a sibling is
+// promoted to the next level unchanged.
+pub fn build_levels(
+    leaves: Vec<Vec<u8>>,
+    combine: impl Fn(&[u8], &[u8]) -> Vec<u8>,
+) -> Vec<Vec<Vec<u8>>> {
+    let mut levels = vec![leaves];
+
+    while levels.last().is_some_and(|level| level.len() > 1) {
+        let current_level = levels.last().unwrap();
+        let next_level = current_level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => combine(left, right),
+                [single] => single.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next_level);
+    }
+
+    levels
+}
+
+// Sibling hashes from the leaf at `index` up to the root. Levels where the
+// node is promoted without a sibling contribute nothing.
+pub fn generate_proof(levels: &[Vec<Vec<u8>>], index: usize) -> Vec<Vec<u8>> {
+    let mut proof = vec![];
+    let mut current_index = index;
+
+    for level in levels.iter().take(levels.len().saturating_sub(1)) {
+        let sibling_index = current_index ^ 1;
+        if let Some(sibling) = level.get(sibling_index) {
+            proof.push(sibling.clone());
+        }
+        current_index /= 2;
+    }
+
+    proof
+}
+
+// Side of each proof element, `true` when the sibling is on the left.
+//
+// With the tree's leaf count the levels where the node is promoted without a
+// sibling are skipped, exactly as generate_proof does, and None is returned
+// when the index is out of range or the proof has the wrong length. Without it
+// the side is taken from the bits of `index`, which goes wrong once the node has
+// been promoted (e.g. the last leaf of an unbalanced tree).
+pub fn sibling_sides(index: u32, leaf_count: Option<u32>, proof_len: usize) -> Option<Vec<bool>> {
+    let mut sides = Vec::with_capacity(proof_len);
+    let mut current_index = index;
+
+    let leaf_count = match leaf_count {
+        Some(leaf_count) => leaf_count,
+        None => {
+            for _ in 0..proof_len {
+                sides.push(current_index & 1 == 1);
+                current_index /= 2;
+            }
+            return Some(sides);
+        }
+    };
+
+    if index >= leaf_count {
+        return None;
+    }
+
+    let mut level_size = leaf_count;
+    while level_size > 1 {
+        if current_index & 1 == 1 {
+            sides.push(true);
+        } else if current_index + 1 < level_size {
+            sides.push(false);
+        }
+        current_index /= 2;
+        level_size = level_size.div_ceil(2);
+    }
+
+    (sides.len() == proof_len).then_some(sides)
+}
+
+// Fold a proof into a root, one side flag per proof element
+pub fn root_from_proof(
+    leaf: Vec<u8>,
+    proof: &[Vec<u8>],
+    sides: &[bool],
+    combine: impl Fn(&[u8], &[u8]) -> Vec<u8>,
+) -> Vec<u8> {
+    proof.iter().zip(sides).fold(leaf, |current_hash, (sibling, &sibling_on_left)| {
+        if sibling_on_left {
+            combine(sibling, &current_hash)
+        } else {
+            combine(&current_hash, sibling)
+        }
+    })
+}
+
+// Sibling hashes needed to prove all leaves at `indices` together, level by
+// level from the leaves up and left to right within a level. Siblings that are
+// themselves proven or computable are left out. `indices` must be sorted,
+// unique and in range.
+pub fn generate_multiproof(levels: &[Vec<Vec<u8>>], indices: &[u32]) -> Vec<Vec<u8>> {
+    let mut proof = vec![];
+    let mut known: Vec<u32> = indices.to_vec();
+
+    for level in levels.iter().take(levels.len().saturating_sub(1)) {
+        let mut next_known = Vec::with_capacity(known.len());
+        let mut i = 0;
+        while i < known.len() {
+            let index = known[i];
+            let sibling_index = index ^ 1;
+            if index & 1 == 0 && known.get(i + 1) == Some(&sibling_index) {
+                // Both children are known
+                i += 1;
+            } else if let Some(sibling) = level.get(sibling_index as usize) {
+                proof.push(sibling.clone());
+            }
+            next_known.push(index / 2);
+            i += 1;
+        }
+        known = next_known;
+    }
+
+    proof
+}
+
+// Fold a multi-proof into a root. `leaves` are (index, leaf) pairs sorted by
+// index without duplicates. Returns None when an index is out of range or the
+// proof has too few or too many elements.
+pub fn root_from_multiproof(
+    leaf_count: u32,
+    leaves: Vec<(u32, Vec<u8>)>,
+    proof: &[Vec<u8>],
+    combine: impl Fn(&[u8], &[u8]) -> Vec<u8>,
+) -> Option<Vec<u8>> {
+    if leaves.is_empty() || leaves.last().is_some_and(|(index, _)| *index >= leaf_count) {
+        return None;
+    }
+
+    let mut proof = proof.iter();
+    let mut known = leaves;
+    let mut level_size = leaf_count;
+
+    while level_size > 1 {
+        let mut next_known = Vec::with_capacity(known.len());
+        let mut nodes = known.into_iter().peekable();
+        while let Some((index, hash)) = nodes.next() {
+            let sibling_index = index ^ 1;
+            let parent = if index & 1 == 0 && nodes.peek().is_some_and(|(next, _)| *next == sibling_index) {
+                let (_, sibling) = nodes.next().unwrap();
+                combine(&hash, &sibling)
+            } else if sibling_index >= level_size {
+                // Promoted without a sibling
+                hash
+            } else {
+                let sibling = proof.next()?;
+                if index & 1 == 1 {
+                    combine(sibling, &hash)
+                } else {
+                    combine(&hash, sibling)
+                }
+            };
+            next_known.push((index / 2, parent));
+        }
+        known = next_known;
+        level_size = level_size.div_ceil(2);
+    }
+
+    if proof.next().is_some() {
+        return None;
+    }
+    known.pop().map(|(_, root)| root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    // Order-sensitive stand-in for a hash function, so the structural checks
+    // don't need the mocked blockchain
+    fn combine(left: &[u8], right: &[u8]) -> Vec<u8> {
+        let mut hasher = DefaultHasher::new();
+        (left, right).hash(&mut hasher);
+        hasher.finish().to_le_bytes().to_vec()
+    }
+
+    fn leaves(count: u32) -> Vec<Vec<u8>> {
+        (0..count).map(|i| i.to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn proofs_verify_for_every_leaf_of_trees_up_to_1025_leaves() {
+        for leaf_count in 1..=1025u32 {
+            let leaves = leaves(leaf_count);
+            let levels = build_levels(leaves.clone(), combine);
+            let root = &levels.last().unwrap()[0];
+
+            for index in 0..leaf_count {
+                let proof = generate_proof(&levels, index as usize);
+                let sides = sibling_sides(index, Some(leaf_count), proof.len())
+                    .unwrap_or_else(|| panic!("no sides for leaf {} of {}", index, leaf_count));
+                let computed = root_from_proof(leaves[index as usize].clone(), &proof, &sides, combine);
+                assert_eq!(&computed, root, "leaf {} of {}", index, leaf_count);
+            }
+        }
+    }
+
+    #[test]
+    fn proofs_are_rejected_for_the_wrong_leaf_count_or_index() {
+        for leaf_count in 1..=1025u32 {
+            let levels = build_levels(leaves(leaf_count), combine);
+            let last = leaf_count - 1;
+            let proof = generate_proof(&levels, last as usize);
+
+            assert!(sibling_sides(leaf_count, Some(leaf_count), proof.len()).is_none());
+            assert!(sibling_sides(last, Some(leaf_count), proof.len() + 1).is_none());
+            if !proof.is_empty() {
+                assert!(sibling_sides(last, Some(leaf_count), proof.len() - 1).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn multiproofs_verify_for_index_subsets_of_trees_up_to_1025_leaves() {
+        for leaf_count in 1..=1025u32 {
+            let leaves = leaves(leaf_count);
+            let levels = build_levels(leaves.clone(), combine);
+            let root = &levels.last().unwrap()[0];
+
+            let subsets: Vec<Vec<u32>> = vec![
+                (0..leaf_count).collect(),
+                vec![leaf_count - 1],
+                vec![0, leaf_count - 1],
+                (0..leaf_count).step_by(3).collect(),
+                (leaf_count / 2..leaf_count).collect(),
+            ];
+            for mut indices in subsets {
+                indices.dedup();
+                let proof = generate_multiproof(&levels, &indices);
+                let proven = indices.iter().map(|&i| (i, leaves[i as usize].clone())).collect();
+                assert_eq!(
+                    root_from_multiproof(leaf_count, proven, &proof, combine).as_ref(),
+                    Some(root),
+                    "indices {:?} of {}", indices, leaf_count
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn multiproofs_are_rejected_with_missing_or_extra_elements() {
+        let leaf_count = 13;
+        let leaves = leaves(leaf_count);
+        let levels = build_levels(leaves.clone(), combine);
+        let indices = [2u32, 3, 9];
+        let proof = generate_multiproof(&levels, &indices);
+        let proven: Vec<(u32, Vec<u8>)> = indices.iter().map(|&i| (i, leaves[i as usize].clone())).collect();
+
+        let mut extra = proof.clone();
+        extra.push(leaves[0].clone());
+        assert!(root_from_multiproof(leaf_count, proven.clone(), &extra, combine).is_none());
+        assert!(root_from_multiproof(leaf_count, proven.clone(), &proof[1..], combine).is_none());
+        assert!(root_from_multiproof(9, proven, &proof, combine).is_none());
+    }
+
+    #[test]
+    fn index_bits_misplace_the_promoted_last_leaf() {
+        // Leaf 4 of 5 is promoted twice, so its only sibling sits on the left
+        // although bit 0 of the index is clear
+        let leaf_count = 5;
+        let leaves = leaves(leaf_count);
+        let levels = build_levels(leaves.clone(), combine);
+        let root = &levels.last().unwrap()[0];
+        let proof = generate_proof(&levels, 4);
+        assert_eq!(proof.len(), 1);
+
+        let with_count = sibling_sides(4, Some(leaf_count), 1).unwrap();
+        let from_bits = sibling_sides(4, None, 1).unwrap();
+        assert_eq!(with_count, vec![true]);
+        assert_eq!(from_bits, vec![false]);
+        assert_eq!(&root_from_proof(leaves[4].clone(), &proof, &with_count, combine), root);
+        assert_ne!(&root_from_proof(leaves[4].clone(), &proof, &from_bits, combine), root);
+    }
+}
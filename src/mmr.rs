@@ -0,0 +1,279 @@
+// Merkle Mountain Range over the batches of one database.
+//
+// Every minted batch appends a leaf committing to its token ID and merkle root.
+// Nodes are kept per level: level 0 holds the leaves in mint order and node `j`
+// of level `k + 1` is the parent of nodes `2j` and `2j + 1` of level `k`, so a
+// range of `n` leaves has `n >> k` nodes at level `k`. The peaks are the last
+// node of every level with an odd node count, highest level (leftmost) first,
+// and the root bags them from the right: node(p0, node(p1, ... node(pn-1, pn))).
+//
+// Hashing is domain separated as in RFC 6962: leaves are sha256(0x00 || data)
+// and nodes sha256(0x01 || left || right).
+//
+// Nodes never change once written, so the peaks of any earlier size are still
+// stored. A consistency proof between two sizes is the old peaks plus, for each
+// of them, the path up to the new peak that contains it.
+//
+// Copyright (c) 2025 Graziano Labs Corp. All rights reserved.
+
+use crate::merkle::{self, HashAlgorithm};
+
+// Leaf committing to a batch: the token ID (length-prefixed) and merkle root
+pub fn leaf_hash(token_id: &str, merkle_root: &str) -> Vec<u8> {
+    let mut data = Vec::with_capacity(4 + token_id.len() + merkle_root.len());
+    data.extend_from_slice(&(token_id.len() as u32).to_le_bytes());
+    data.extend_from_slice(token_id.as_bytes());
+    data.extend_from_slice(merkle_root.as_bytes());
+    merkle::rfc6962_leaf_hash(HashAlgorithm::Sha256, &data)
+}
+
+pub fn node_hash(left: &[u8], right: &[u8]) -> Vec<u8> {
+    merkle::rfc6962_node_hash(HashAlgorithm::Sha256, left, right)
+}
+
+// Nodes to store when appending `leaf` to a range of `leaf_count` leaves, as
+// (level, index, hash). `node` reads an existing node.
+pub fn append(
+    leaf_count: u64,
+    leaf: Vec<u8>,
+    node: impl Fn(u8, u64) -> Vec<u8>,
+) -> Vec<(u8, u64, Vec<u8>)> {
+    let mut current = leaf;
+    let mut level = 0u8;
+    let mut index = leaf_count;
+    let mut nodes = vec![(level, index, current.clone())];
+
+    // A right child completes its parent
+    while index & 1 == 1 {
+        current = node_hash(&node(level, index - 1), &current);
+        level += 1;
+        index >>= 1;
+        nodes.push((level, index, current.clone()));
+    }
+
+    nodes
+}
+
+// (level, index) of the peaks of a range of `leaf_count` leaves, leftmost first
+pub fn peak_positions(leaf_count: u64) -> Vec<(u8, u64)> {
+    (0..u64::BITS as u8)
+        .rev()
+        .filter(|&level| (leaf_count >> level) & 1 == 1)
+        .map(|level| (level, (leaf_count >> level) - 1))
+        .collect()
+}
+
+pub fn peaks(leaf_count: u64, node: impl Fn(u8, u64) -> Vec<u8>) -> Vec<Vec<u8>> {
+    peak_positions(leaf_count)
+        .into_iter()
+        .map(|(level, index)| node(level, index))
+        .collect()
+}
+
+pub fn bag_peaks(peaks: &[Vec<u8>]) -> Option<Vec<u8>> {
+    let (last, rest) = peaks.split_last()?;
+    Some(rest.iter().rev().fold(last.clone(), |acc, peak| node_hash(peak, &acc)))
+}
+
+// Number of levels from the node at (level, index) up to the peak containing
+// it in a range of `leaf_count` leaves, and the position of that peak
+fn climb(level: u8, index: u64, leaf_count: u64) -> (usize, usize) {
+    let nodes_above = |level: u8| leaf_count.checked_shr(level as u32 + 1).unwrap_or(0);
+    let mut level = level;
+    let mut index = index;
+    let mut steps = 0;
+
+    // Climb while the parent exists
+    while (index >> 1) < nodes_above(level) {
+        level += 1;
+        index >>= 1;
+        steps += 1;
+    }
+
+    (steps, nodes_above(level).count_ones() as usize)
+}
+
+// Sibling hashes from the node at (level, index) up to its peak, and the
+// position of that peak in `peaks`
+pub fn path_to_peak(
+    level: u8,
+    index: u64,
+    leaf_count: u64,
+    node: impl Fn(u8, u64) -> Vec<u8>,
+) -> (Vec<Vec<u8>>, usize) {
+    let (steps, peak_position) = climb(level, index, leaf_count);
+    let siblings = (0..steps)
+        .map(|step| node(level + step as u8, (index >> step) ^ 1))
+        .collect();
+    (siblings, peak_position)
+}
+
+// Sibling hashes from leaf `leaf_index` up to its peak, and the position of
+// that peak in `peaks`
+pub fn inclusion_proof(
+    leaf_index: u64,
+    leaf_count: u64,
+    node: impl Fn(u8, u64) -> Vec<u8>,
+) -> (Vec<Vec<u8>>, usize) {
+    path_to_peak(0, leaf_index, leaf_count, node)
+}
+
+// Fold a path into the node at its top. The sibling's side is given by the
+// bits of `index`, as every peak is a perfect tree.
+pub fn root_from_path(node: Vec<u8>, index: u64, siblings: &[Vec<u8>]) -> Vec<u8> {
+    siblings.iter().enumerate().fold(node, |current, (step, sibling)| {
+        if (index >> step) & 1 == 1 {
+            node_hash(sibling, &current)
+        } else {
+            node_hash(&current, sibling)
+        }
+    })
+}
+
+// Check that the range of `new_size` leaves with root `new_root` extends the
+// range of `old_size` leaves with root `old_root`. `old_peak_paths[i]` leads
+// from `old_peaks[i]` to the new peak containing it.
+pub fn verify_consistency(
+    old_size: u64,
+    old_root: &[u8],
+    new_size: u64,
+    new_root: &[u8],
+    old_peaks: &[Vec<u8>],
+    old_peak_paths: &[Vec<Vec<u8>>],
+    new_peaks: &[Vec<u8>],
+) -> bool {
+    if old_size == 0 || old_size > new_size {
+        return false;
+    }
+
+    let old_positions = peak_positions(old_size);
+    if old_peaks.len() != old_positions.len()
+        || old_peak_paths.len() != old_positions.len()
+        || new_peaks.len() != new_size.count_ones() as usize
+    {
+        return false;
+    }
+
+    if bag_peaks(old_peaks).as_deref() != Some(old_root)
+        || bag_peaks(new_peaks).as_deref() != Some(new_root)
+    {
+        return false;
+    }
+
+    old_positions.iter().zip(old_peaks).zip(old_peak_paths).all(|((&(level, index), peak), path)| {
+        let (steps, peak_position) = climb(level, index, new_size);
+        path.len() == steps && root_from_path(peak.clone(), index, path) == new_peaks[peak_position]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::testing_env;
+    use std::collections::HashMap;
+
+    // Peaks built from scratch: one perfect tree per set bit of the leaf count
+    fn reference_peaks(leaves: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        let mut peaks = vec![];
+        let mut start = 0;
+        for level in (0..u64::BITS).rev() {
+            let width = 1usize << level;
+            if leaves.len() & width != 0 {
+                let tree = merkle::build_levels(leaves[start..start + width].to_vec(), node_hash);
+                peaks.push(tree.last().unwrap()[0].clone());
+                start += width;
+            }
+        }
+        peaks
+    }
+
+    #[test]
+    fn every_leaf_proves_into_the_root_as_the_range_grows() {
+        let mut nodes: HashMap<(u8, u64), Vec<u8>> = HashMap::new();
+        let mut leaves = vec![];
+
+        for leaf_count in 0..100u64 {
+            // Fresh gas budget for each append
+            testing_env!(VMContextBuilder::new().build());
+
+            let leaf = leaf_hash(&format!("batch-{}", leaf_count), "root");
+            for (level, index, hash) in append(leaf_count, leaf.clone(), |l, i| nodes[&(l, i)].clone()) {
+                nodes.insert((level, index), hash);
+            }
+            leaves.push(leaf);
+
+            let size = leaf_count + 1;
+            let peaks = peaks(size, |l, i| nodes[&(l, i)].clone());
+            assert_eq!(peaks, reference_peaks(&leaves));
+
+            for (leaf_index, leaf) in leaves.iter().enumerate() {
+                let (siblings, position) =
+                    inclusion_proof(leaf_index as u64, size, |l, i| nodes[&(l, i)].clone());
+                let peak = root_from_path(leaf.clone(), leaf_index as u64, &siblings);
+                assert_eq!(peak, peaks[position], "leaf {} of {}", leaf_index, size);
+            }
+        }
+    }
+
+    #[test]
+    fn every_pair_of_sizes_is_consistent() {
+        let mut nodes: HashMap<(u8, u64), Vec<u8>> = HashMap::new();
+        let mut roots = vec![];
+
+        for leaf_count in 0..40u64 {
+            testing_env!(VMContextBuilder::new().build());
+            let leaf = leaf_hash(&format!("batch-{}", leaf_count), "root");
+            for (level, index, hash) in append(leaf_count, leaf, |l, i| nodes[&(l, i)].clone()) {
+                nodes.insert((level, index), hash);
+            }
+            roots.push(bag_peaks(&peaks(leaf_count + 1, |l, i| nodes[&(l, i)].clone())).unwrap());
+        }
+
+        let node = |l: u8, i: u64| nodes[&(l, i)].clone();
+        for old_size in 1..=40u64 {
+            testing_env!(VMContextBuilder::new().build());
+            for new_size in old_size..=40 {
+                let old_peaks = peaks(old_size, node);
+                let paths: Vec<Vec<Vec<u8>>> = peak_positions(old_size)
+                    .into_iter()
+                    .map(|(level, index)| path_to_peak(level, index, new_size, node).0)
+                    .collect();
+                let new_peaks = peaks(new_size, node);
+                let old_root = &roots[old_size as usize - 1];
+                let new_root = &roots[new_size as usize - 1];
+
+                assert!(
+                    verify_consistency(old_size, old_root, new_size, new_root, &old_peaks, &paths, &new_peaks),
+                    "{} -> {}", old_size, new_size
+                );
+                if new_size > old_size {
+                    // A rewritten history or swapped snapshots must not verify
+                    let other_root = &roots[new_size as usize - 2];
+                    assert!(!verify_consistency(
+                        old_size, old_root, new_size, other_root, &old_peaks, &paths, &new_peaks,
+                    ));
+                    assert!(!verify_consistency(
+                        new_size, new_root, old_size, old_root, &new_peaks, &paths, &old_peaks,
+                    ));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn root_bags_peaks_from_the_right() {
+        testing_env!(VMContextBuilder::new().build());
+        let peaks: Vec<Vec<u8>> = (0..3u8).map(|i| vec![i; 32]).collect();
+        let expected = node_hash(&peaks[0], &node_hash(&peaks[1], &peaks[2]));
+        assert_eq!(bag_peaks(&peaks), Some(expected));
+        assert_eq!(bag_peaks(&peaks[..1]), Some(peaks[0].clone()));
+        assert_eq!(bag_peaks(&[]), None);
+    }
+
+    #[test]
+    fn leaves_commit_to_token_and_root() {
+        assert_ne!(leaf_hash("ab", "c"), leaf_hash("a", "bc"));
+        assert_ne!(leaf_hash("a", "root1"), leaf_hash("a", "root2"));
+    }
+}
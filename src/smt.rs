@@ -0,0 +1,160 @@
+// Sparse Merkle tree of the latest row versions of a database.
+//
+// Every row has a 256-bit key, sha256(len(table) as u32 LE || table || primary
+// key), which is its path from the root: bit 0 (the most significant bit of
+// the first byte) picks the root's child. Leaves sit at depth 256.
+//
+// An empty subtree hashes to 32 zero bytes at every height, so a parent of two
+// empty subtrees is empty as well. Everything else is domain separated as in
+// RFC 6962: a row is sha256(0x00 || key || row_hash) and a node
+// sha256(0x01 || left || right).
+//
+// Copyright (c) 2025 Graziano Labs Corp. All rights reserved.
+
+use crate::merkle::{self, HashAlgorithm};
+
+pub const DEPTH: usize = 256;
+pub const EMPTY: [u8; 32] = [0; 32];
+
+pub fn row_key(table_name: &str, primary_key_hash: &str) -> Vec<u8> {
+    let primary_key = merkle::leaf_data(primary_key_hash);
+    let mut data = Vec::with_capacity(4 + table_name.len() + primary_key.len());
+    data.extend_from_slice(&(table_name.len() as u32).to_le_bytes());
+    data.extend_from_slice(table_name.as_bytes());
+    data.extend_from_slice(&primary_key);
+    HashAlgorithm::Sha256.digest(&data)
+}
+
+pub fn leaf_hash(key: &[u8], row_hash: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(key.len() + row_hash.len());
+    data.extend_from_slice(key);
+    data.extend_from_slice(row_hash);
+    merkle::rfc6962_leaf_hash(HashAlgorithm::Sha256, &data)
+}
+
+pub fn node_hash(left: &[u8], right: &[u8]) -> Vec<u8> {
+    if left == EMPTY && right == EMPTY {
+        return EMPTY.to_vec();
+    }
+    merkle::rfc6962_node_hash(HashAlgorithm::Sha256, left, right)
+}
+
+fn bit(key: &[u8], depth: usize) -> bool {
+    (key[depth / 8] >> (7 - depth % 8)) & 1 == 1
+}
+
+// Root of the subtree at `depth` holding `leaves`, which are (key, leaf hash)
+// pairs sorted by key. The siblings along `path`, if given, are collected
+// leaf level first.
+fn subtree(
+    leaves: &[(Vec<u8>, Vec<u8>)],
+    depth: usize,
+    path: Option<&[u8]>,
+    siblings: &mut Vec<Vec<u8>>,
+) -> Vec<u8> {
+    if leaves.is_empty() && path.is_none() {
+        return EMPTY.to_vec();
+    }
+    if depth == DEPTH {
+        return leaves.first().map_or(EMPTY.to_vec(), |(_, leaf)| leaf.clone());
+    }
+
+    // Keys are sorted, so the left child's leaves come first
+    let split = leaves.partition_point(|(key, _)| !bit(key, depth));
+    let (left, right) = leaves.split_at(split);
+    let on_right = path.map(|path| bit(path, depth));
+    let left_hash = subtree(left, depth + 1, path.filter(|_| on_right == Some(false)), siblings);
+    let right_hash = subtree(right, depth + 1, path.filter(|_| on_right == Some(true)), siblings);
+
+    match on_right {
+        Some(true) => siblings.push(left_hash.clone()),
+        Some(false) => siblings.push(right_hash.clone()),
+        None => {}
+    }
+    node_hash(&left_hash, &right_hash)
+}
+
+// Root of a tree holding `leaves`, which must be sorted by key without
+// duplicates
+pub fn root(leaves: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+    subtree(leaves, 0, None, &mut vec![])
+}
+
+// The DEPTH siblings of `key`, leaf level first
+pub fn proof(leaves: &[(Vec<u8>, Vec<u8>)], key: &[u8]) -> Vec<Vec<u8>> {
+    let mut siblings = Vec::with_capacity(DEPTH);
+    subtree(leaves, 0, Some(key), &mut siblings);
+    siblings
+}
+
+// Fold the leaf at `key` (EMPTY for an absent row) up to the root
+pub fn root_from_proof(key: &[u8], leaf: Vec<u8>, siblings: &[Vec<u8>]) -> Option<Vec<u8>> {
+    if key.len() != 32 || siblings.len() != DEPTH {
+        return None;
+    }
+    Some(siblings.iter().enumerate().fold(leaf, |current, (height, sibling)| {
+        if bit(key, DEPTH - 1 - height) {
+            node_hash(sibling, &current)
+        } else {
+            node_hash(&current, sibling)
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::testing_env;
+
+    fn leaves(count: u8) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut leaves: Vec<(Vec<u8>, Vec<u8>)> = (0..count)
+            .map(|i| {
+                let key = row_key("users", &format!("pk-{}", i));
+                let leaf = leaf_hash(&key, &[i; 32]);
+                (key, leaf)
+            })
+            .collect();
+        leaves.sort();
+        leaves
+    }
+
+    #[test]
+    fn present_and_absent_rows_prove_into_the_root() {
+        for count in [0u8, 1, 2, 5] {
+            testing_env!(VMContextBuilder::new().build());
+            let leaves = leaves(count);
+            let root = root(&leaves);
+
+            for (key, leaf) in &leaves {
+                testing_env!(VMContextBuilder::new().build());
+                let siblings = proof(&leaves, key);
+                assert_eq!(root_from_proof(key, leaf.clone(), &siblings), Some(root.clone()));
+                assert_ne!(root_from_proof(key, EMPTY.to_vec(), &siblings), Some(root.clone()));
+            }
+
+            testing_env!(VMContextBuilder::new().build());
+            let absent = row_key("users", "missing");
+            let siblings = proof(&leaves, &absent);
+            assert_eq!(root_from_proof(&absent, EMPTY.to_vec(), &siblings), Some(root.clone()));
+            assert_ne!(
+                root_from_proof(&absent, leaf_hash(&absent, &[0; 32]), &siblings),
+                Some(root),
+            );
+        }
+    }
+
+    #[test]
+    fn empty_tree_has_the_empty_root() {
+        testing_env!(VMContextBuilder::new().build());
+        assert_eq!(root(&[]), EMPTY.to_vec());
+        assert_eq!(proof(&[], &EMPTY), vec![EMPTY.to_vec(); DEPTH]);
+    }
+
+    #[test]
+    fn keys_separate_tables() {
+        testing_env!(VMContextBuilder::new().build());
+        assert_ne!(row_key("users", "1"), row_key("orders", "1"));
+        assert_ne!(row_key("ab", "c"), row_key("a", "bc"));
+    }
+}